@@ -1,43 +1,262 @@
 use crate::error::Error;
-use crate::state::{State, Utxo};
+use crate::rpc;
+use crate::state::{HistoryEntry, State, Utxo};
+use crate::style;
 use crate::util;
+use itertools::Itertools;
+use miniscript::bitcoin::hashes::hex::ToHex;
+use miniscript::bitcoin::util::address::Payload;
 use miniscript::{bitcoin, Descriptor};
+use qrcode::render::unicode;
+use qrcode::QrCode;
 
+/// Compute the address of a descriptor without touching the state
+pub fn get_address(
+    state: &State,
+    descriptor: &Descriptor<bitcoin::XOnlyPublicKey>,
+) -> Result<bitcoin::Address, Error> {
+    util::verify_taproot(descriptor)?;
+
+    Ok(descriptor.address(state.network).unwrap())
+}
+
+/// Check that the given address is the address of the descriptor
+///
+/// On mismatch, print both witness programs; for taproot this is the output key
+pub fn verify_address(
+    state: &State,
+    address: &bitcoin::Address,
+    descriptor: &Descriptor<bitcoin::XOnlyPublicKey>,
+) -> Result<(), Error> {
+    if !address.is_valid_for_network(state.network) {
+        return Err(Error::WrongNetwork);
+    }
+    let expected = get_address(state, descriptor)?;
+    println!("Expected address: {}", expected);
+    println!("Given address:    {}", address);
+
+    if expected.script_pubkey() == address.script_pubkey() {
+        println!("{}", style::added("Address matches the descriptor"));
+        return Ok(());
+    }
+
+    let describe_program = |address: &bitcoin::Address| match &address.payload {
+        Payload::WitnessProgram { version, program } => {
+            format!("{} (witness v{})", program.to_hex(), version.to_num())
+        }
+        _ => "none (not a witness program)".to_string(),
+    };
+    println!("Expected witness program: {}", describe_program(&expected));
+    println!("Given witness program:    {}", describe_program(address));
+    Err(Error::AddressMismatch)
+}
+
+/// Get the address of the inbound address with the given index,
+/// or with the lowest index if none is given
+pub fn get_inbound_address(state: &State, index: Option<usize>) -> Result<bitcoin::Address, Error> {
+    let index = match index {
+        Some(index) => index,
+        None => *state
+            .inbound_addresses
+            .keys()
+            .min()
+            .ok_or(Error::MissingAddress)?,
+    };
+    let descriptor = state
+        .inbound_addresses
+        .get(&index)
+        .ok_or(Error::MissingAddress)?;
+    get_address(state, descriptor)
+}
+
+/// Print the address as QR code made of Unicode blocks
+///
+/// The address is uppercased, which makes the QR code of bech32 addresses smaller
+pub fn print_qr(address: &bitcoin::Address) -> Result<(), Error> {
+    let code = QrCode::new(address.to_string().to_uppercase())?;
+    // Light modules are drawn, which suits terminals with a dark background
+    let image = code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build();
+    println!("{}", image);
+
+    Ok(())
+}
+
+/// Add a descriptor to the inbound addresses
+///
+/// Returns the index of the inbound address and the address itself
 pub fn set_address(
     state: &mut State,
     descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
-) -> Result<bitcoin::Address, Error> {
-    util::verify_taproot(&descriptor)?;
+) -> Result<(usize, bitcoin::Address), Error> {
+    let address = get_address(state, &descriptor)?;
+    let index = state
+        .inbound_addresses
+        .keys()
+        .max()
+        .map_or(0, |index| index + 1);
+    state.inbound_addresses.insert(index, descriptor);
+
+    Ok((index, address))
+}
 
-    let address = descriptor.address(bitcoin::Network::Regtest).unwrap();
-    state.inbound_address = Some(descriptor);
+/// Add an inbound address that is spent by the key path of the given key only
+///
+/// The descriptor has no script tree, so the address works with any taproot wallet
+pub fn set_keypath_address(
+    state: &mut State,
+    pubkey: bitcoin::XOnlyPublicKey,
+) -> Result<(usize, bitcoin::Address), Error> {
+    let descriptor = Descriptor::new_tr(pubkey, None)?;
+    set_address(state, descriptor)
+}
 
-    Ok(address)
+pub fn delete_address(
+    state: &mut State,
+    index: usize,
+) -> Result<Descriptor<bitcoin::XOnlyPublicKey>, Error> {
+    state
+        .inbound_addresses
+        .remove(&index)
+        .ok_or(Error::MissingAddress)
 }
 
 pub fn into_utxo(
     state: &mut State,
+    index: usize,
     txid: bitcoin::Txid,
     output_index: u32,
     value: u64,
+    verify: bool,
 ) -> Result<(), Error> {
-    let descriptor = state.inbound_address.take().ok_or(Error::MissingAddress)?;
+    let descriptor = state
+        .inbound_addresses
+        .get(&index)
+        .ok_or(Error::MissingAddress)?;
+
+    if verify {
+        let tx = rpc::get_transaction(txid)?;
+        let txout = tx
+            .output
+            .get(output_index as usize)
+            .ok_or(Error::UtxoMismatch)?;
+
+        if txout.script_pubkey != descriptor.script_pubkey() || txout.value != value {
+            return Err(Error::UtxoMismatch);
+        }
+    }
+
+    let descriptor = state
+        .inbound_addresses
+        .remove(&index)
+        .expect("checked above");
+    let outpoint = bitcoin::OutPoint {
+        txid,
+        vout: output_index,
+    };
+    add_utxo(state, descriptor, outpoint, value);
+
+    Ok(())
+}
+
+/// Return the Bitcoin Core command line that sends the value to the address
+pub fn fund_command(address: &bitcoin::Address, value: u64) -> String {
+    let amount = bitcoin::Amount::from_sat(value).to_string_in(bitcoin::Denomination::Bitcoin);
+    rpc::command_line("sendtoaddress", &[&address.to_string(), &amount])
+}
+
+/// Find the output of a funding transaction that pays to the inbound address
+///
+/// The transaction is the JSON of Bitcoin Core's `gettransaction` or
+/// `getrawtransaction <txid> true`, which contain its hex.
+/// Returns the txid, the output index and the value
+pub fn find_funding_output(
+    state: &State,
+    index: usize,
+    json: &str,
+) -> Result<(bitcoin::Txid, u32, u64), Error> {
+    let descriptor = state
+        .inbound_addresses
+        .get(&index)
+        .ok_or(Error::MissingAddress)?;
+    let response: serde_json::Value = serde_json::from_str(json)?;
+    let tx_hex = response["hex"].as_str().ok_or(Error::InvalidFundingJson)?;
+    let tx = util::parse_transaction(tx_hex)?;
+    let script_pubkey = descriptor.script_pubkey();
+
+    let (vout, txout) = tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, txout)| txout.script_pubkey == script_pubkey)
+        .ok_or(Error::NoMatchingOutput)?;
+
+    Ok((tx.txid(), vout as u32, txout.value))
+}
+
+/// Convert the unspent outputs of the inbound addresses into UTXOs
+///
+/// The unspent outputs are found by scanning the UTXO set of Bitcoin Core.
+/// Inbound addresses without unspent outputs are kept.
+pub fn scan_addresses(state: &mut State) -> Result<(), Error> {
+    if state.inbound_addresses.is_empty() {
+        return Err(Error::MissingAddress);
+    }
+
+    for index in state
+        .inbound_addresses
+        .keys()
+        .copied()
+        .sorted()
+        .collect::<Vec<_>>()
+    {
+        let address = get_address(state, &state.inbound_addresses[&index])?;
+        let unspents = rpc::scan_address(&address)?;
+
+        if unspents.is_empty() {
+            println!("No UTXO found for {} (yet)", address);
+            continue;
+        }
+
+        let descriptor = state.inbound_addresses.remove(&index).expect("key exists");
+        for (outpoint, value) in unspents {
+            add_utxo(state, descriptor.clone(), outpoint, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn add_utxo(
+    state: &mut State,
+    descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    outpoint: bitcoin::OutPoint,
+    value: u64,
+) {
     let utxo = Utxo {
         output: bitcoin::TxOut {
             value,
             script_pubkey: descriptor.script_pubkey(),
         },
         descriptor,
-        outpoint: bitcoin::OutPoint {
-            txid,
-            vout: output_index,
-        },
+        outpoint,
     };
 
+    state.history.push(HistoryEntry {
+        txid: outpoint.txid,
+        spent: Vec::new(),
+        created: vec![utxo.clone()],
+        spend: None,
+    });
+
     if !state.utxos.contains(&utxo) {
-        println!("New UTXO #{}: {}", state.utxos.len(), utxo);
+        println!(
+            "{}",
+            style::added(format_args!("New UTXO #{}: {}", state.utxos.len(), utxo))
+        );
         state.utxos.push(utxo);
     }
-
-    Ok(())
 }
@@ -0,0 +1,143 @@
+use crate::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Maximum number of backups that are kept per state file
+pub const MAX_BACKUPS: usize = 10;
+
+fn backup_prefix(path: &Path) -> OsString {
+    let mut prefix = path.file_name().unwrap_or_default().to_owned();
+    prefix.push(".bak.");
+    prefix
+}
+
+/// List backups of the given state file as (unix timestamp, path), oldest first
+pub fn list_backups(path: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let prefix = backup_prefix(path);
+    let prefix = prefix.to_string_lossy();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut backups = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        if let Some(timestamp) = file_name.strip_prefix(prefix.as_ref()) {
+            if let Ok(timestamp) = timestamp.parse::<u64>() {
+                backups.push((timestamp, entry.path()));
+            }
+        }
+    }
+
+    backups.sort();
+    Ok(backups)
+}
+
+/// Copy the given state file to a timestamped backup
+/// and delete the oldest backups so that at most `keep` remain
+///
+/// Does nothing if the state file does not exist
+pub fn create_backup(path: &Path, keep: usize) -> Result<(), Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(format!(".bak.{}", timestamp));
+    let backup_path = PathBuf::from(backup_path);
+
+    // Keep the older backup if several commands run within the same second
+    if !backup_path.exists() {
+        fs::copy(path, &backup_path)?;
+    }
+
+    prune_backups(path, keep)?;
+    Ok(())
+}
+
+/// Delete the oldest backups of the given state file so that at most `keep` remain
+///
+/// Returns the deleted backups
+pub fn prune_backups(path: &Path, keep: usize) -> Result<Vec<PathBuf>, Error> {
+    let backups = list_backups(path)?;
+    let excess = backups.len().saturating_sub(keep);
+    let mut deleted = Vec::new();
+
+    for (_, backup_path) in backups.into_iter().take(excess) {
+        fs::remove_file(&backup_path)?;
+        deleted.push(backup_path);
+    }
+
+    Ok(deleted)
+}
+
+/// Find the backup of the given state file that was made at the given unix timestamp
+pub fn find_backup(path: &Path, timestamp: u64) -> Result<PathBuf, Error> {
+    list_backups(path)?
+        .into_iter()
+        .find(|(backup_timestamp, _)| *backup_timestamp == timestamp)
+        .map(|(_, backup_path)| backup_path)
+        .ok_or(Error::MissingBackup(timestamp))
+}
+
+/// Copy the given backup over the given state file
+///
+/// The current state file is saved as undo file beforehand
+pub fn restore_backup(path: &Path, backup_path: &Path) -> Result<(), Error> {
+    save_undo(path)?;
+    fs::copy(backup_path, path)?;
+    Ok(())
+}
+
+/// Path of the undo file of the given state file
+pub fn undo_path(path: &Path) -> PathBuf {
+    let mut undo_path = path.as_os_str().to_owned();
+    undo_path.push(".undo");
+    PathBuf::from(undo_path)
+}
+
+/// Copy the given state file to its undo file, replacing the previous one
+///
+/// Does nothing if the state file does not exist
+pub fn save_undo(path: &Path) -> Result<(), Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    fs::copy(path, undo_path(path))?;
+    Ok(())
+}
+
+/// Swap the given state file with its undo file
+///
+/// Undoing twice restores the state before the first undo
+pub fn undo(path: &Path) -> Result<(), Error> {
+    let undo_path = undo_path(path);
+
+    let mut swap_path = path.as_os_str().to_owned();
+    swap_path.push(".swap");
+    let swap_path = PathBuf::from(swap_path);
+
+    fs::rename(path, &swap_path)?;
+    fs::rename(&undo_path, path)?;
+    fs::rename(&swap_path, &undo_path)?;
+    Ok(())
+}
+
+/// Delete the undo file of the given state file, if there is one
+pub fn clear_undo(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(undo_path(path)) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
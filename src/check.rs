@@ -0,0 +1,260 @@
+use crate::descriptor;
+use crate::state::{Input, State};
+use crate::style;
+use crate::util;
+use itertools::Itertools;
+use miniscript::bitcoin::hashes::{hash160, ripemd160, sha256, Hash};
+use miniscript::bitcoin::secp256k1::schnorr;
+use miniscript::bitcoin::util::taproot::TapLeafHash;
+use miniscript::bitcoin::{LockTime, SchnorrSighashType, Sequence};
+use miniscript::{bitcoin, hash256, Descriptor, MiniscriptKey, Preimage32, Satisfier, ToPublicKey};
+use std::collections::BTreeSet;
+
+/// Run the checks that spending would run, without signing or saving
+///
+/// Prints one line per check and returns the number of failed checks
+pub fn check_transaction(state: &State) -> usize {
+    let mut results = Vec::new();
+
+    let contiguous = |indices: Vec<&usize>| {
+        indices
+            .into_iter()
+            .sorted()
+            .enumerate()
+            .all(|(expected_index, index)| expected_index == *index)
+    };
+    results.push((
+        "Transaction has at least one input".to_string(),
+        !state.inputs.is_empty(),
+    ));
+    results.push((
+        "Input indices have no gaps".to_string(),
+        contiguous(state.inputs.keys().collect()),
+    ));
+    results.push((
+        "Output indices have no gaps".to_string(),
+        contiguous(state.outputs.keys().collect()),
+    ));
+    results.push((
+        "Inputs cover the outputs and the fee".to_string(),
+        util::get_remaining_funds(state).is_ok()
+            && util::get_spent_funds(state) <= util::get_input_funds(state),
+    ));
+    if util::get_fee_output(state).is_some() {
+        results.push((
+            "Output that pays the fee stays above the dust limit".to_string(),
+            util::get_fee_output_value(state).is_ok(),
+        ));
+    }
+    results.push((
+        "No input funds go to the fee unassigned".to_string(),
+        util::check_unassigned_funds(state).is_ok(),
+    ));
+
+    for index in state.inputs.keys().sorted() {
+        let input = &state.inputs[index];
+        results.push((
+            format!("Input {} uses a taproot descriptor", index),
+            util::verify_taproot(&input.utxo.descriptor).is_ok(),
+        ));
+        results.push((
+            format!(
+                "Input {} can be satisfied with the enabled keys and images",
+                index
+            ),
+            can_satisfy(state, input),
+        ));
+
+        let is_single = matches!(
+            input.sighash,
+            SchnorrSighashType::Single | SchnorrSighashType::SinglePlusAnyoneCanPay
+        );
+        if is_single {
+            results.push((
+                format!("Input {} with SIGHASH_SINGLE has an output", index),
+                state.outputs.contains_key(index),
+            ));
+        }
+    }
+
+    for (description, passed) in &results {
+        if *passed {
+            println!("{}", style::added(format_args!("pass: {}", description)));
+        } else {
+            println!("{}", style::error(format_args!("FAIL: {}", description)));
+        }
+    }
+
+    let failed = results.iter().filter(|(_, passed)| !passed).count();
+    println!(
+        "\n{} of {} checks passed",
+        results.len() - failed,
+        results.len()
+    );
+    print_unused_secrets(state);
+    failed
+}
+
+/// Warn about enabled keys and images that no input references
+///
+/// Spending could sign with them although no input needs them
+fn print_unused_secrets(state: &State) {
+    let mut keys = BTreeSet::new();
+    let mut images = BTreeSet::new();
+    for input in state.inputs.values() {
+        if let Ok(summary) = descriptor::summarize(&input.utxo.descriptor) {
+            keys.extend(summary.keys);
+            images.extend(summary.images);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for key in state.active_keys.keys() {
+        let xonly = util::into_xonly(*key).to_string();
+        if !keys.contains(&xonly) {
+            warnings.push(format!("Enabled key {} is not used by any input", xonly));
+        }
+    }
+    for (image, preimage) in &state.active_images {
+        let fragments = [
+            format!("sha256({})", image),
+            format!("hash256({})", hash256::Hash::hash(preimage)),
+            format!("ripemd160({})", ripemd160::Hash::hash(preimage)),
+            format!("hash160({})", hash160::Hash::hash(preimage)),
+        ];
+        if !fragments.iter().any(|fragment| images.contains(fragment)) {
+            warnings.push(format!("Enabled image {} is not used by any input", image));
+        }
+    }
+
+    if !warnings.is_empty() {
+        println!();
+    }
+    for warning in warnings.into_iter().sorted() {
+        println!("{}", style::error(format_args!("Warning: {}", warning)));
+    }
+}
+
+/// Return true if the enabled keys and images satisfy the descriptor of the input
+/// under the current timelocks
+fn can_satisfy(state: &State, input: &Input) -> bool {
+    let internal_key = match &input.utxo.descriptor {
+        Descriptor::Tr(tr) => tr.internal_key().to_public_key(),
+        _ => return false,
+    };
+    let satisfier = DryRunSatisfier {
+        state,
+        internal_key,
+        sequence: input.sequence,
+        sighash_type: input.sighash,
+    };
+    input.utxo.descriptor.get_satisfaction(satisfier).is_ok()
+}
+
+/// Satisfier that pretends to sign with the enabled keys
+struct DryRunSatisfier<'a> {
+    state: &'a State,
+    internal_key: bitcoin::PublicKey,
+    sequence: Sequence,
+    sighash_type: SchnorrSighashType,
+}
+
+impl<'a> DryRunSatisfier<'a> {
+    fn dummy_signature(&self, pk: &bitcoin::PublicKey) -> Option<bitcoin::SchnorrSig> {
+        if !self.state.active_keys.contains_key(pk) {
+            return None;
+        }
+        let sig = schnorr::Signature::from_slice(&[1; 64]).expect("64 bytes");
+        Some(bitcoin::SchnorrSig {
+            sig,
+            hash_ty: self.sighash_type,
+        })
+    }
+
+    fn find_preimage<F>(&self, matches: F) -> Option<Preimage32>
+    where
+        F: Fn(&Preimage32) -> bool,
+    {
+        self.state
+            .active_images
+            .values()
+            .find(|p| matches(p))
+            .copied()
+    }
+}
+
+impl<'a, Pk> Satisfier<Pk> for DryRunSatisfier<'a>
+where
+    Pk: MiniscriptKey<Sha256 = sha256::Hash> + ToPublicKey,
+{
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        self.dummy_signature(&self.internal_key)
+    }
+
+    fn lookup_tap_leaf_script_sig(
+        &self,
+        pk: &Pk,
+        _leaf_hash: &TapLeafHash,
+    ) -> Option<bitcoin::SchnorrSig> {
+        self.dummy_signature(&pk.to_public_key())
+    }
+
+    fn lookup_sha256(&self, image: &Pk::Sha256) -> Option<Preimage32> {
+        self.state.active_images.get(image.as_ref()).copied()
+    }
+
+    fn lookup_hash256(&self, image: &Pk::Hash256) -> Option<Preimage32> {
+        let image = Pk::to_hash256(image);
+        self.find_preimage(|preimage| hash256::Hash::hash(preimage) == image)
+    }
+
+    fn lookup_ripemd160(&self, image: &Pk::Ripemd160) -> Option<Preimage32> {
+        let image = Pk::to_ripemd160(image);
+        self.find_preimage(|preimage| ripemd160::Hash::hash(preimage) == image)
+    }
+
+    fn lookup_hash160(&self, image: &Pk::Hash160) -> Option<Preimage32> {
+        let image = Pk::to_hash160(image);
+        self.find_preimage(|preimage| hash160::Hash::hash(preimage) == image)
+    }
+
+    fn check_older(&self, sequence: Sequence) -> bool {
+        <Sequence as Satisfier<Pk>>::check_older(&self.sequence, sequence)
+    }
+
+    fn check_after(&self, locktime: LockTime) -> bool {
+        <LockTime as Satisfier<Pk>>::check_after(&self.state.locktime, locktime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::new_descriptor;
+    use crate::{input, output};
+
+    /// State with one input spending to an enabled key and one output receiving the rest
+    fn spendable_state() -> State {
+        let mut state = State::new();
+        let descriptor = new_descriptor(&mut state);
+        let outpoint = bitcoin::OutPoint::default();
+        input::add_from_outpoint(&mut state, 0, outpoint, 100_000, descriptor.clone()).unwrap();
+        output::add_output(&mut state, 0, descriptor, 0, 1).unwrap();
+        state.fee = 200;
+        state
+    }
+
+    #[test]
+    fn spendable_state_passes() {
+        assert_eq!(check_transaction(&spendable_state()), 0);
+    }
+
+    #[test]
+    fn gap_in_input_indices_fails() {
+        let mut state = spendable_state();
+        let input = state.inputs.remove(&0).unwrap();
+        state.inputs.insert(1, input);
+
+        assert!(check_transaction(&state) > 0);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::Error;
+use miniscript::bitcoin;
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Config file that is used if no other file is given
+pub const DEFAULT_CONFIG_FILE: &str = "tappy.toml";
+
+/// Defaults from the config file
+///
+/// Command line flags and environment variables take precedence
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Network of new state files
+    pub network: Option<bitcoin::Network>,
+    /// Fee in sat of new state files
+    pub fee: Option<u64>,
+    /// Target feerate in sat / vB of new state files
+    pub feerate: Option<f64>,
+    /// Feerate in sat / vB to use if Bitcoin Core has no estimate
+    pub fallback_feerate: Option<f64>,
+    /// Feerate in sat / vB below which spending warns
+    pub min_feerate: Option<f64>,
+    /// Feerate in sat / vB above which spending warns
+    pub max_feerate: Option<f64>,
+    /// Address of Bitcoin Core (host or host:port)
+    pub rpc_connect: Option<String>,
+    /// Cookie file for authenticating with Bitcoin Core
+    pub rpc_cookie: Option<PathBuf>,
+}
+
+impl Config {
+    /// Load the config from the given path, or from the default file
+    ///
+    /// A missing default file yields the empty config;
+    /// a missing file that was explicitly given is an error
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let (path, explicit) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_CONFIG_FILE), false),
+        };
+        let config_error = |error: &dyn std::fmt::Display| {
+            Error::ConfigFile(path.to_path_buf(), error.to_string())
+        };
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !explicit => {
+                return Ok(Self::default());
+            }
+            Err(error) => return Err(config_error(&error)),
+        };
+        let config: Self = toml::from_str(&text).map_err(|error| config_error(&error))?;
+
+        for feerate in [
+            config.feerate,
+            config.fallback_feerate,
+            config.min_feerate,
+            config.max_feerate,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !feerate.is_finite() || feerate < 0.0 {
+                return Err(config_error(&"feerates must be non-negative numbers"));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn missing_default_file_is_empty_config() {
+        // Tests run in the crate root, which has no config file
+        assert!(!Path::new(DEFAULT_CONFIG_FILE).exists());
+        let config = Config::load(None).unwrap();
+        assert!(config.network.is_none() && config.fee.is_none());
+    }
+
+    #[test]
+    fn missing_explicit_file_fails() {
+        let path = test_dir("config-missing").join("tappy.toml");
+        assert!(matches!(
+            Config::load(Some(&path)),
+            Err(Error::ConfigFile(..))
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unknown_key_fails() {
+        let path = test_dir("config-unknown").join("tappy.toml");
+        std::fs::write(&path, "fee = 200\nfees = 300\n").unwrap();
+        assert!(matches!(
+            Config::load(Some(&path)),
+            Err(Error::ConfigFile(..))
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn negative_feerate_fails() {
+        let path = test_dir("config-negative").join("tappy.toml");
+        std::fs::write(&path, "feerate = -1.0\n").unwrap();
+        assert!(matches!(
+            Config::load(Some(&path)),
+            Err(Error::ConfigFile(..))
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn explicit_file_is_loaded() {
+        let path = test_dir("config-load").join("tappy.toml");
+        std::fs::write(&path, "network = \"testnet\"\nfee = 200\n").unwrap();
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(config.network, Some(bitcoin::Network::Testnet));
+        assert_eq!(config.fee, Some(200));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use crate::error::Error;
+use miniscript::bitcoin::util::taproot::{LeafVersion, TapLeafHash, TaprootBuilder};
+use miniscript::descriptor::TapTree;
+use miniscript::{bitcoin, Descriptor, Miniscript, Tap, Terminal};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Parse a taproot descriptor
+///
+/// If parsing fails, the error names the innermost fragment that does not parse
+/// on its own, together with its byte position
+pub fn parse(descriptor: &str) -> Result<Descriptor<bitcoin::XOnlyPublicKey>, Error> {
+    let error = match Descriptor::from_str(descriptor) {
+        Ok(descriptor) => return Ok(descriptor),
+        Err(error) => error,
+    };
+
+    match find_invalid_fragment(descriptor) {
+        Some((position, fragment, message)) => Err(Error::InvalidDescriptor(
+            position,
+            fragment.to_string(),
+            message,
+        )),
+        None => Err(error.into()),
+    }
+}
+
+/// Find the shortest fragment `name(...)` that is not valid tapscript miniscript
+///
+/// Fragments are checked without sanity checks, so that errors of the
+/// whole descriptor (such as duplicate keys) are not blamed on a part of it
+fn find_invalid_fragment(descriptor: &str) -> Option<(usize, &str, String)> {
+    let bytes = descriptor.as_bytes();
+    let mut open = Vec::new();
+    let mut invalid: Option<(usize, &str, String)> = None;
+
+    for (position, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'(' => open.push(position),
+            b')' => {
+                let paren = open.pop()?;
+                // Fragment starts after the previous separator, including wrappers like v:
+                let start = bytes[..paren]
+                    .iter()
+                    .rposition(|byte| matches!(byte, b'(' | b',' | b'{' | b'}'))
+                    .map_or(0, |separator| separator + 1);
+                let fragment = &descriptor[start..=position];
+                if start == 0 {
+                    // The descriptor itself, such as tr(...)
+                    continue;
+                }
+                if let Err(error) =
+                    Miniscript::<bitcoin::XOnlyPublicKey, Tap>::from_str_insane(fragment)
+                {
+                    let is_shorter = invalid
+                        .as_ref()
+                        .is_none_or(|(_, other, _)| fragment.len() < other.len());
+                    if is_shorter {
+                        invalid = Some((start, fragment, error.to_string()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    invalid
+}
+
+/// Parts of a descriptor as strings, for comparing descriptors
+///
+/// Images are written as miniscript fragments, such as `sha256(<hex>)`
+#[derive(Default)]
+pub(crate) struct Summary {
+    internal_key: String,
+    leaves: BTreeSet<String>,
+    pub(crate) keys: BTreeSet<String>,
+    pub(crate) images: BTreeSet<String>,
+    timelocks: BTreeSet<String>,
+}
+
+pub(crate) fn summarize(
+    descriptor: &Descriptor<bitcoin::XOnlyPublicKey>,
+) -> Result<Summary, Error> {
+    let tr = match descriptor {
+        Descriptor::Tr(tr) => tr,
+        _ => return Err(Error::OnlyTaproot),
+    };
+    let mut summary = Summary {
+        internal_key: tr.internal_key().to_string(),
+        ..Default::default()
+    };
+    summary.keys.insert(tr.internal_key().to_string());
+
+    for (depth, ms) in tr.iter_scripts() {
+        summary.leaves.insert(format!("depth {}: {}", depth, ms));
+
+        for node in ms.iter() {
+            match &node.node {
+                Terminal::PkK(pk) | Terminal::PkH(pk) => {
+                    summary.keys.insert(pk.to_string());
+                }
+                Terminal::Multi(_, pks) | Terminal::MultiA(_, pks) => {
+                    for pk in pks {
+                        summary.keys.insert(pk.to_string());
+                    }
+                }
+                Terminal::Sha256(image) => {
+                    summary.images.insert(format!("sha256({})", image));
+                }
+                Terminal::Hash256(image) => {
+                    summary.images.insert(format!("hash256({})", image));
+                }
+                Terminal::Ripemd160(image) => {
+                    summary.images.insert(format!("ripemd160({})", image));
+                }
+                Terminal::Hash160(image) => {
+                    summary.images.insert(format!("hash160({})", image));
+                }
+                Terminal::After(locktime) => {
+                    summary.timelocks.insert(format!("after({})", locktime));
+                }
+                Terminal::Older(sequence) => {
+                    summary.timelocks.insert(format!("older({})", sequence));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn print_set_diff(name: &str, first: &BTreeSet<String>, second: &BTreeSet<String>) {
+    println!("{}:", name);
+    for removed in first.difference(second) {
+        println!("  - {}", removed);
+    }
+    for added in second.difference(first) {
+        println!("  + {}", added);
+    }
+    if first == second {
+        println!("  (unchanged)");
+    }
+}
+
+pub fn diff_descriptors(
+    first: &Descriptor<bitcoin::XOnlyPublicKey>,
+    second: &Descriptor<bitcoin::XOnlyPublicKey>,
+) -> Result<(), Error> {
+    let first = summarize(first)?;
+    let second = summarize(second)?;
+
+    println!("Internal key:");
+    if first.internal_key == second.internal_key {
+        println!("  (unchanged)");
+    } else {
+        println!("  - {}", first.internal_key);
+        println!("  + {}", second.internal_key);
+    }
+    print_set_diff("Leaves", &first.leaves, &second.leaves);
+    print_set_diff("Keys", &first.keys, &second.keys);
+    print_set_diff("Images", &first.images, &second.images);
+    print_set_diff("Timelocks", &first.timelocks, &second.timelocks);
+
+    Ok(())
+}
+
+/// Print the Taproot internals of a descriptor
+pub fn print_info(descriptor: &Descriptor<bitcoin::XOnlyPublicKey>) -> Result<(), Error> {
+    let tr = match descriptor {
+        Descriptor::Tr(tr) => tr,
+        _ => return Err(Error::OnlyTaproot),
+    };
+    let info = tr.spend_info();
+
+    println!("Internal key: {}", info.internal_key());
+    match info.merkle_root() {
+        Some(merkle_root) => println!("Merkle root: {}", merkle_root),
+        None => println!("Merkle root: (none)"),
+    }
+    println!("Output key: {}", info.output_key());
+    println!("Output key parity: {:?}", info.output_key_parity());
+    println!("Script pubkey: {:x}", descriptor.script_pubkey());
+
+    for (depth, ms) in tr.iter_scripts() {
+        let script = ms.encode();
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+        println!("Leaf (depth {}): {}", depth, ms);
+        println!("  Script: {:x}", script);
+        println!("  Version: 0x{:02x}", LeafVersion::TapScript.to_consensus());
+        println!("  Leaf hash: {}", leaf_hash);
+    }
+
+    Ok(())
+}
+
+/// Print the worst-case witness weight of the key path and of each leaf
+///
+/// Signatures count with a sighash byte; leaves that cannot be satisfied are marked
+pub fn print_leaf_weights(descriptor: &Descriptor<bitcoin::XOnlyPublicKey>) -> Result<(), Error> {
+    let tr = match descriptor {
+        Descriptor::Tr(tr) => tr,
+        _ => return Err(Error::OnlyTaproot),
+    };
+    let varint_len = |n: usize| bitcoin::VarInt(n as u64).len();
+
+    // Stack length, signature length, signature with sighash byte
+    let key_path_weight = 1 + 1 + 65;
+    println!("Key path: {} WU", key_path_weight);
+
+    for (depth, ms) in tr.iter_scripts() {
+        let (elements, satisfaction_size) = match (
+            ms.max_satisfaction_witness_elements(),
+            ms.max_satisfaction_size(),
+        ) {
+            (Ok(elements), Ok(size)) => (elements, size),
+            _ => {
+                println!("Leaf (depth {}): {}: cannot be satisfied", depth, ms);
+                continue;
+            }
+        };
+        let script_size = ms.script_size();
+        let control_block_size = 33 + 32 * usize::from(depth);
+        // Satisfaction items, then the script and the control block
+        let weight = varint_len(elements + 2)
+            + satisfaction_size
+            + varint_len(script_size)
+            + script_size
+            + varint_len(control_block_size)
+            + control_block_size;
+        println!(
+            "Leaf (depth {}): {}: {} WU (script {} B, control block {} B)",
+            depth, ms, weight, script_size, control_block_size
+        );
+    }
+
+    Ok(())
+}
+
+/// Build a taproot descriptor from leaves with explicit depths
+///
+/// Leaves are given in depth-first order, from left to right.
+/// Leaves closer to the root have shorter control blocks and cheaper witnesses
+pub fn build_tree(
+    internal_key: bitcoin::XOnlyPublicKey,
+    leaves: Vec<(u8, Miniscript<bitcoin::XOnlyPublicKey, Tap>)>,
+) -> Result<Descriptor<bitcoin::XOnlyPublicKey>, Error> {
+    if leaves.is_empty() {
+        return Ok(Descriptor::new_tr(internal_key, None)?);
+    }
+
+    let mut builder = TaprootBuilder::new();
+    for (depth, ms) in &leaves {
+        builder = builder.add_leaf(*depth, ms.encode())?;
+    }
+    if !builder.is_finalizable() {
+        return Err(Error::IncompleteTapTree);
+    }
+
+    // Merge siblings of equal depth, like the builder does
+    let mut branches: Vec<(u8, TapTree<bitcoin::XOnlyPublicKey>)> = Vec::new();
+    for (depth, ms) in leaves {
+        let mut node = (depth, TapTree::Leaf(Arc::new(ms)));
+        while let Some((sibling_depth, _)) = branches.last() {
+            if *sibling_depth != node.0 {
+                break;
+            }
+            let (_, sibling) = branches.pop().expect("checked above");
+            node = (
+                node.0 - 1,
+                TapTree::Tree(Arc::new(sibling), Arc::new(node.1)),
+            );
+        }
+        branches.push(node);
+    }
+    let (_, tree) = branches.pop().expect("builder is finalizable");
+
+    Ok(Descriptor::new_tr(internal_key, Some(tree))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f";
+
+    fn invalid_fragment(descriptor: &str) -> (usize, String) {
+        match parse(descriptor) {
+            Err(Error::InvalidDescriptor(position, fragment, _)) => (position, fragment),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(descriptor) => panic!("unexpectedly valid: {}", descriptor),
+        }
+    }
+
+    #[test]
+    fn valid_descriptor_parses() {
+        let descriptor = format!("tr({},and_v(v:pk({}),older(10)))", KEY, KEY);
+        assert!(parse(&descriptor).is_ok());
+    }
+
+    #[test]
+    fn bad_key_names_its_fragment() {
+        let descriptor = format!("tr({},{{pk({}),and_v(v:pk(zz),older(10))}})", KEY, KEY);
+        let (position, fragment) = invalid_fragment(&descriptor);
+        assert_eq!(fragment, "v:pk(zz)");
+        assert_eq!(&descriptor[position..position + fragment.len()], fragment);
+    }
+
+    #[test]
+    fn type_error_names_the_combinator() {
+        let descriptor = format!("tr({},and_v(pk({}),older(10)))", KEY, KEY);
+        let (_, fragment) = invalid_fragment(&descriptor);
+        assert_eq!(fragment, format!("and_v(pk({}),older(10))", KEY));
+    }
+
+    #[test]
+    fn bad_hash_names_its_fragment() {
+        let descriptor = format!("tr({},sha256(abc))", KEY);
+        let (_, fragment) = invalid_fragment(&descriptor);
+        assert_eq!(fragment, "sha256(abc)");
+    }
+
+    #[test]
+    fn bad_internal_key_keeps_plain_error() {
+        assert!(matches!(parse("tr(zz)"), Err(Error::Miniscript(_))));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::state::State;
+use crate::util;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Print one "before → after" line per item that differs between the two states
+///
+/// Secret keys and preimages are never printed
+pub fn print_changes(before: &State, after: &State) {
+    print_change("network", &before.network, &after.network);
+    print_entries("keys", key_entries(before), key_entries(after));
+    print_entries("images", image_entries(before), image_entries(after));
+    print_entries(
+        "inbound_addresses",
+        display_entries(&before.inbound_addresses),
+        display_entries(&after.inbound_addresses),
+    );
+    print_entries("utxos", utxo_entries(before), utxo_entries(after));
+    print_entries(
+        "inputs",
+        display_entries(&before.inputs),
+        display_entries(&after.inputs),
+    );
+    print_entries(
+        "outputs",
+        display_entries(&before.outputs),
+        display_entries(&after.outputs),
+    );
+    print_change("tx_version", &before.tx_version, &after.tx_version);
+    print_change("locktime", &before.locktime, &after.locktime);
+    print_change("fee", &before.fee, &after.fee);
+    print_change(
+        "feerate",
+        &describe_option(before.feerate),
+        &describe_option(after.feerate),
+    );
+    print_entries(
+        "templates",
+        display_entries(&before.templates),
+        display_entries(&after.templates),
+    );
+    print_change(
+        "history",
+        &format!("{} entries", before.history.len()),
+        &format!("{} entries", after.history.len()),
+    );
+    print_change(
+        "last_spend",
+        &describe_option(before.last_spend.as_ref().map(|s| s.transaction.txid())),
+        &describe_option(after.last_spend.as_ref().map(|s| s.transaction.txid())),
+    );
+}
+
+fn print_change<T: fmt::Display + PartialEq>(name: &str, before: &T, after: &T) {
+    if before != after {
+        println!("{}: {} → {}", name, before, after);
+    }
+}
+
+fn print_entries<K: Ord + fmt::Display>(
+    name: &str,
+    before: BTreeMap<K, String>,
+    after: BTreeMap<K, String>,
+) {
+    let none = "none".to_string();
+    let keys: BTreeSet<&K> = before.keys().chain(after.keys()).collect();
+
+    for key in keys {
+        let old = before.get(key).unwrap_or(&none);
+        let new = after.get(key).unwrap_or(&none);
+        print_change(&format!("{}[{}]", name, key), old, new);
+    }
+}
+
+fn describe_option<T: fmt::Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+fn display_entries<K, V, I>(items: I) -> BTreeMap<K, String>
+where
+    K: Ord,
+    V: fmt::Display,
+    I: IntoIterator<Item = (K, V)>,
+{
+    items
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect()
+}
+
+/// Describe each key by its status and label, without secrets
+fn key_entries(state: &State) -> BTreeMap<String, String> {
+    let statuses = state
+        .active_keys
+        .keys()
+        .map(|key| (key, "enabled"))
+        .chain(state.passive_keys.keys().map(|key| (key, "disabled")))
+        .chain(state.watch_keys.iter().map(|key| (key, "watch-only")));
+
+    statuses
+        .map(|(key, status)| {
+            let description = match state.key_labels.get(key) {
+                Some(label) => format!("{} ({})", status, label),
+                None => status.to_string(),
+            };
+            (util::into_xonly(*key).to_string(), description)
+        })
+        .collect()
+}
+
+/// Describe each image by its status and label, without preimages
+fn image_entries(state: &State) -> BTreeMap<String, String> {
+    let statuses = state
+        .active_images
+        .keys()
+        .map(|image| (image, "enabled"))
+        .chain(state.passive_images.keys().map(|image| (image, "disabled")))
+        .chain(state.watch_images.iter().map(|image| (image, "watch-only")));
+
+    statuses
+        .map(|(image, status)| {
+            let description = match state.image_labels.get(image) {
+                Some(label) => format!("{} ({})", status, label),
+                None => status.to_string(),
+            };
+            (image.to_string(), description)
+        })
+        .collect()
+}
+
+/// Describe each UTXO by outpoint, so removing one does not shift the others
+fn utxo_entries(state: &State) -> BTreeMap<String, String> {
+    state
+        .utxos
+        .iter()
+        .map(|utxo| {
+            let description = match state.utxo_labels.get(&utxo.outpoint) {
+                Some(label) => format!("{} ({})", utxo, label),
+                None => utxo.to_string(),
+            };
+            (utxo.outpoint.to_string(), description)
+        })
+        .collect()
+}
@@ -1,4 +1,9 @@
-use miniscript::bitcoin::hashes::hex;
+use miniscript::bitcoin;
+use miniscript::bitcoin::consensus::encode;
+use miniscript::bitcoin::hashes::{hex, sha256};
+use miniscript::bitcoin::psbt;
+use miniscript::bitcoin::util::{bip32, sighash, taproot};
+use std::path::PathBuf;
 use std::{fmt, io};
 use thiserror::Error;
 
@@ -12,26 +17,152 @@ pub enum Error {
     Miniscript(#[from] miniscript::Error),
     #[error("{0}")]
     Hex(#[from] hex::Error),
+    #[error("{0}")]
+    Encode(#[from] encode::Error),
+    #[error("{0}")]
+    Bip32(#[from] bip32::Error),
+    #[error("{0}")]
+    Psbt(#[from] psbt::Error),
+    #[error("{0}")]
+    Sighash(#[from] sighash::Error),
+    #[error("Invalid tap tree: {0}")]
+    TaprootBuilder(#[from] taproot::TaprootBuilderError),
+    #[error("{0}")]
+    QrCode(#[from] qrcode::types::QrError),
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(bip39::Error),
+    #[error("Bitcoin Core: {0}")]
+    Rpc(String),
+    #[error("State file version {0} is newer than this binary supports")]
+    UnsupportedVersion(u64),
     #[error("Inbound address is missing")]
     MissingAddress,
+    #[error("Transaction output does not pay the given value to the inbound address")]
+    UtxoMismatch,
     #[error("No UTXO at index")]
     MissingUtxo,
+    #[error("No transaction output matches the given descriptors or the inbound address")]
+    NoMatchingOutput,
+    #[error(
+        "Expected the JSON of gettransaction or getrawtransaction <txid> true, with a hex field"
+    )]
+    InvalidFundingJson,
+    #[error("UTXO entry {0}: {1}")]
+    UtxoEntry(usize, String),
     #[error("Input is missing")]
     MissingInput,
     #[error("Output is missing")]
     MissingOutput,
+    #[error("Missing input at index {0} (indices must be contiguous from 0)")]
+    MissingInputIndex(usize),
+    #[error("Missing output at index {0} (indices must be contiguous from 0)")]
+    MissingOutputIndex(usize),
     #[error("Unknown public key")]
     UnknownKey,
+    #[error("Could not generate a public key that is not already known")]
+    DuplicateKey,
+    #[error("Key pair is already known")]
+    KnownKey,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Neither valid hex nor a known label: {0}")]
+    UnknownLabel(String),
+    #[error("Label {0} is used {1} times; use the hex value instead")]
+    AmbiguousLabel(String, usize),
     #[error("Unknown hash image")]
     UnknownImage,
+    #[error("(Pre)image pair is already known")]
+    KnownImage,
+    #[error("Preimage must be exactly 32 bytes")]
+    InvalidPreimage,
+    #[error("Relative timelock cannot exceed 33553920 seconds (65535 * 512)")]
+    InvalidRelativeTime,
+    #[error("Locktime is disabled because no input enables it; set the sequence of an input first (for instance `in <INDEX> seq enable 0`)")]
+    LocktimeDisabled,
+    #[error("OP_RETURN data cannot exceed 80 bytes")]
+    DataTooLong,
+    #[error("Input {0} uses SIGHASH_SINGLE but there is no output with the same index")]
+    SingleWithoutOutput(usize),
+    #[error("PSBT input is missing the output that it spends")]
+    MissingWitnessUtxo,
+    #[error("Cannot sign: secret key of watch-only key {0} is unknown")]
+    WatchOnlyKey(bitcoin::XOnlyPublicKey),
+    #[error("Cannot satisfy hashlock: preimage of watch-only image {0} is unknown")]
+    WatchOnlyImage(sha256::Hash),
     #[error("Not enough funds to fund remaining output")]
     NotEnoughFunds,
+    #[error("Invalid tap tree: leaf depths do not form a complete binary tree")]
+    IncompleteTapTree,
+    #[error("Invalid descriptor at byte {0}, in `{1}`: {2}")]
+    InvalidDescriptor(usize, String, String),
     #[error("Only Taproot descriptors are supported")]
     OnlyTaproot,
-    #[error("At most one output can have zero value")]
-    OneZeroOutput,
+    #[error("Output pays to an address without descriptor")]
+    NotDescriptor,
+    #[error("Address does not match the descriptor")]
+    AddressMismatch,
+    #[error("Address is not valid for this network")]
+    WrongNetwork,
+    #[error("Output weight must be at least 1")]
+    ZeroWeight,
+    #[error("Only outputs with zero value (that receive the remaining funds) can have a weight")]
+    WeightWithoutRemaining,
+    #[error("An output cannot pay the fee while zero-value outputs receive the remaining funds")]
+    FeeOutputWithRemaining,
+    #[error("Output {0} cannot pay the fee without going below {1} sat (dust limit)")]
+    FeeOutputDust(usize, u64),
+    #[error("Output value must be at least {0} sat (dust limit)")]
+    DustOutput(u64),
+    #[error("Output order must list every output index exactly once")]
+    InvalidOutputOrder,
     #[error("Same UTXO can be used at most once as input")]
     DoubleSpend,
+    #[error("No history to rebuild from")]
+    MissingHistory,
+    #[error("{0} sat of the inputs are not assigned to any output and would go to the fee")]
+    UnassignedFunds(u64),
+    #[error("This discards the current transaction; pass --force to proceed")]
+    ForceRequired,
+    #[error("Input {0} failed the local witness check: {1}")]
+    WitnessCheck(usize, miniscript::interpreter::Error),
+    #[error("{}: {1}", .0.display())]
+    DescriptorFile(PathBuf, String),
+    #[error("Config file {}: {1}", .0.display())]
+    ConfigFile(PathBuf, String),
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(String),
+    #[error("Txid {0} does not match the last spent transaction {1}")]
+    TxidMismatch(bitcoin::Txid, bitcoin::Txid),
+    #[error("No spent transaction to finalize; run spend first")]
+    MissingSpend,
+    #[error("Transaction {0} was not spent by tappy")]
+    UnknownSpend(bitcoin::Txid),
+    #[error("Cannot replace {0}: a later transaction spends its outputs")]
+    SpentReplacement(bitcoin::Txid),
+    #[error("Several outputs could receive the change; choose one with --change")]
+    AmbiguousChange,
+    #[error("Vanity prefix must be hex digits")]
+    InvalidVanityPrefix,
+    #[error("Vanity prefix cannot exceed {0} hex digits; longer prefixes take too long to find")]
+    VanityPrefixTooLong(usize),
+    #[error("No key with the vanity prefix found after {0} attempts")]
+    VanityNotFound(u64),
+    #[error("State file is inconsistent:\n{}", .0.join("\n"))]
+    InconsistentState(Vec<String>),
+    #[error("No backup with timestamp {0}")]
+    MissingBackup(u64),
+    #[error("There is no change to undo")]
+    NothingToUndo,
+    #[error("{0} checks failed")]
+    ChecksFailed(usize),
+    #[error("Aborted")]
+    Aborted,
+    #[error("Unknown template")]
+    MissingTemplate,
+    #[error("Template placeholders must be numbered $0, $1, ... without gaps")]
+    InvalidTemplate,
+    #[error("Template expects {0} arguments but {1} were given")]
+    TemplateArguments(usize, usize),
 }
 
 impl fmt::Debug for Error {
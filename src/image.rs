@@ -1,23 +1,121 @@
 use crate::error::Error;
 use crate::state::State;
-use miniscript::bitcoin::hashes::{sha256, Hash};
+use crate::style;
+use crate::util;
+use miniscript::bitcoin::hashes::hex::FromHex;
+use miniscript::bitcoin::hashes::{hash160, ripemd160, sha256, Hash};
 use miniscript::bitcoin::secp256k1;
 use miniscript::bitcoin::secp256k1::rand::Rng;
-use miniscript::Preimage32;
+use miniscript::{bitcoin, hash256, Preimage32, ToPublicKey};
+use rayon::prelude::*;
 
 pub fn generate_images(state: &mut State, number: u32) -> Result<(), Error> {
-    let mut rng = secp256k1::rand::rngs::OsRng;
+    let random_pair = |_| {
+        let preimage: Preimage32 = secp256k1::rand::rngs::OsRng.gen();
+        (sha256::Hash::hash(&preimage), preimage)
+    };
+    let pairs: Vec<_> = if number < util::PARALLEL_THRESHOLD {
+        (0..number).map(random_pair).collect()
+    } else {
+        (0..number).into_par_iter().map(random_pair).collect()
+    };
 
-    for _ in 0..number {
-        let preimage: Preimage32 = rng.gen();
-        let image = sha256::Hash::hash(&preimage);
-        println!("New image: {}", image);
+    for (image, preimage) in pairs {
+        println!("{}", style::added(format_args!("New image: {}", image)));
         state.passive_images.insert(image, preimage);
     }
 
     Ok(())
 }
 
+/// Derive (pre)image pair from an enabled key pair
+///
+/// The preimage is SHA-256(secret key || index),
+/// where the index is encoded as 4 bytes big-endian
+pub fn derive_image(
+    state: &mut State,
+    pubkey: bitcoin::XOnlyPublicKey,
+    index: u32,
+) -> Result<(), Error> {
+    let keypair = state
+        .active_keys
+        .get(&pubkey.to_public_key())
+        .ok_or(Error::UnknownKey)?;
+
+    let mut data = keypair.secret_key().secret_bytes().to_vec();
+    data.extend_from_slice(&index.to_be_bytes());
+    let preimage: Preimage32 = sha256::Hash::hash(&data).into_inner();
+    let image = sha256::Hash::hash(&preimage);
+
+    if state.passive_images.contains_key(&image) || state.active_images.contains_key(&image) {
+        return Err(Error::KnownImage);
+    }
+
+    println!("{}", style::added(format_args!("New image: {}", image)));
+    state.watch_images.remove(&image);
+    state.passive_images.insert(image, preimage);
+    Ok(())
+}
+
+/// Import a (pre)image pair from a 32-byte preimage in hex
+pub fn import_image(state: &mut State, preimage_hex: &str) -> Result<sha256::Hash, Error> {
+    let bytes = Vec::<u8>::from_hex(preimage_hex)?;
+    let preimage: Preimage32 = bytes.try_into().map_err(|_| Error::InvalidPreimage)?;
+    let image = sha256::Hash::hash(&preimage);
+
+    if state.passive_images.contains_key(&image) || state.active_images.contains_key(&image) {
+        return Err(Error::KnownImage);
+    }
+
+    state.watch_images.remove(&image);
+    state.passive_images.insert(image, preimage);
+    Ok(image)
+}
+
+/// Watch an image whose preimage is unknown
+///
+/// Descriptors may use the image, but its hashlock cannot be satisfied
+pub fn watch_image(state: &mut State, image: sha256::Hash) -> Result<(), Error> {
+    if is_known(state, &image) {
+        return Err(Error::KnownImage);
+    }
+
+    state.watch_images.insert(image);
+    Ok(())
+}
+
+/// Print the digests of a preimage for each hash type that descriptors support
+pub fn print_digests(state: &State, image: sha256::Hash) -> Result<(), Error> {
+    let preimage = state
+        .active_images
+        .get(&image)
+        .or_else(|| state.passive_images.get(&image))
+        .ok_or(Error::UnknownImage)?;
+
+    println!("sha256: {}", image);
+    println!("hash256: {}", hash256::Hash::hash(preimage));
+    println!("ripemd160: {}", ripemd160::Hash::hash(preimage));
+    println!("hash160: {}", hash160::Hash::hash(preimage));
+
+    Ok(())
+}
+
+fn is_known(state: &State, image: &sha256::Hash) -> bool {
+    state.passive_images.contains_key(image)
+        || state.active_images.contains_key(image)
+        || state.watch_images.contains(image)
+}
+
+/// Resolve an image given as hex or as label
+///
+/// Hex is tried first; only if it does not parse is the input looked up as label
+pub fn resolve_image(state: &State, image_or_label: &str) -> Result<sha256::Hash, Error> {
+    if let Ok(image) = image_or_label.parse() {
+        return Ok(image);
+    }
+    util::find_by_label(&state.image_labels, image_or_label)
+}
+
 pub fn enable_image(state: &mut State, image: sha256::Hash) -> Result<(), Error> {
     let preimage = state
         .passive_images
@@ -38,12 +136,92 @@ pub fn disable_image(state: &mut State, image: sha256::Hash) -> Result<(), Error
     Ok(())
 }
 
-pub fn delete_image(state: &mut State, image: &sha256::Hash) -> Result<Preimage32, Error> {
+/// Enable all (pre)image pairs and return how many were disabled before
+pub fn enable_all_images(state: &mut State) -> usize {
+    let number = state.passive_images.len();
+    state.active_images.extend(state.passive_images.drain());
+    number
+}
+
+/// Disable all (pre)image pairs and return how many were enabled before
+pub fn disable_all_images(state: &mut State) -> usize {
+    let number = state.active_images.len();
+    state.passive_images.extend(state.active_images.drain());
+    number
+}
+
+/// Delete (pre)image pair
+///
+/// Returns the preimage, or `None` for watch-only images
+pub fn delete_image(state: &mut State, image: &sha256::Hash) -> Result<Option<Preimage32>, Error> {
+    state.image_labels.remove(image);
+
     if let Some(preimage) = state.active_images.remove(image) {
-        Ok(preimage)
+        Ok(Some(preimage))
     } else if let Some(preimage) = state.passive_images.remove(image) {
-        Ok(preimage)
+        Ok(Some(preimage))
+    } else if state.watch_images.remove(image) {
+        Ok(None)
     } else {
         Err(Error::UnknownImage)
     }
 }
+
+/// Set label of (pre)image pair, or remove it if no label is given
+pub fn label_image(
+    state: &mut State,
+    image: sha256::Hash,
+    label: Option<String>,
+) -> Result<(), Error> {
+    if !is_known(state, &image) {
+        return Err(Error::UnknownImage);
+    }
+
+    match label {
+        Some(label) => state.image_labels.insert(image, label),
+        None => state.image_labels.remove(&image),
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::enable_new_key;
+
+    fn state_with_active_key() -> (State, bitcoin::XOnlyPublicKey) {
+        let mut state = State::new();
+        let pubkey = enable_new_key(&mut state);
+        (state, pubkey)
+    }
+
+    #[test]
+    fn derive_known_passive_image_fails() {
+        let (mut state, pubkey) = state_with_active_key();
+        derive_image(&mut state, pubkey, 0).unwrap();
+        assert_eq!(state.passive_images.len(), 1);
+
+        assert!(matches!(
+            derive_image(&mut state, pubkey, 0),
+            Err(Error::KnownImage)
+        ));
+        assert_eq!(state.passive_images.len(), 1);
+    }
+
+    #[test]
+    fn derive_known_active_image_fails() {
+        let (mut state, pubkey) = state_with_active_key();
+        derive_image(&mut state, pubkey, 0).unwrap();
+        let (image, preimage) = state.passive_images.drain().next().unwrap();
+        state.active_images.insert(image, preimage);
+
+        assert!(matches!(
+            derive_image(&mut state, pubkey, 0),
+            Err(Error::KnownImage)
+        ));
+        assert!(state.passive_images.is_empty());
+        derive_image(&mut state, pubkey, 1).unwrap();
+        assert_eq!(state.passive_images.len(), 1);
+    }
+}
@@ -1,7 +1,11 @@
+use crate::address;
 use crate::error::Error;
-use crate::state::{Input, State};
+use crate::state::{Input, State, Utxo};
+use crate::style;
+use crate::util;
 use itertools::Itertools;
-use miniscript::bitcoin::Sequence;
+use miniscript::bitcoin::{SchnorrSighashType, Sequence};
+use miniscript::{bitcoin, Descriptor};
 
 pub fn add_from_utxo(
     state: &mut State,
@@ -9,24 +13,178 @@ pub fn add_from_utxo(
     utxo_index: usize,
 ) -> Result<Option<Input>, Error> {
     let utxo = state.utxos.get(utxo_index).ok_or(Error::MissingUtxo)?;
+    insert_input(state, input_index, utxo.clone())
+}
+
+/// Add input that spends an outpoint which is not in the UTXO set
+pub fn add_from_outpoint(
+    state: &mut State,
+    input_index: usize,
+    outpoint: bitcoin::OutPoint,
+    value: u64,
+    descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+) -> Result<Option<Input>, Error> {
+    util::verify_taproot(&descriptor)?;
+
+    let utxo = Utxo {
+        outpoint,
+        output: bitcoin::TxOut {
+            value,
+            script_pubkey: descriptor.script_pubkey(),
+        },
+        descriptor,
+    };
+    insert_input(state, input_index, utxo)
+}
+
+fn insert_input(state: &mut State, input_index: usize, utxo: Utxo) -> Result<Option<Input>, Error> {
     let input = Input {
-        utxo: utxo.clone(),
+        utxo,
         sequence: Sequence::MAX,
+        sighash: SchnorrSighashType::All,
     };
-    if state.inputs.values().contains(&input) {
+    // The input at the same index is replaced, so it does not count
+    if state
+        .inputs
+        .iter()
+        .any(|(i, other)| *i != input_index && other.utxo.outpoint == input.utxo.outpoint)
+    {
         return Err(Error::DoubleSpend);
     }
 
-    println!("New input #{}: {}", input_index, input);
+    let external = if state.is_external(&input) {
+        " (external)"
+    } else {
+        ""
+    };
+    println!(
+        "{}",
+        style::added(format_args!(
+            "New input #{}: {}{}",
+            input_index, input, external
+        ))
+    );
     let old = state.inputs.insert(input_index, input);
 
     Ok(old)
 }
 
+/// Order in which UTXOs are selected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectOrder {
+    LargestFirst,
+    SmallestFirst,
+}
+
+/// Add UTXOs as inputs until they cover the target plus the fee
+///
+/// UTXOs are taken in the given order of value, skipping those that are already spent by an input.
+/// New inputs take the free indices starting at the given index.
+///
+/// Returns the change (selected funds minus target minus fee)
+pub fn select_utxos(
+    state: &mut State,
+    start_index: usize,
+    target: u64,
+    order: SelectOrder,
+) -> Result<u64, Error> {
+    let needed = target + state.fee;
+    let candidates = state
+        .utxos
+        .iter()
+        .enumerate()
+        .filter(|(_, utxo)| {
+            !state
+                .inputs
+                .values()
+                .any(|input| input.utxo.outpoint == utxo.outpoint)
+        })
+        .sorted_by_key(|(_, utxo)| utxo.output.value);
+    let candidates: Vec<(usize, u64)> = match order {
+        SelectOrder::SmallestFirst => candidates.map(|(i, utxo)| (i, utxo.output.value)).collect(),
+        SelectOrder::LargestFirst => candidates
+            .rev()
+            .map(|(i, utxo)| (i, utxo.output.value))
+            .collect(),
+    };
+
+    let mut selected = Vec::new();
+    let mut funds = 0;
+    for (utxo_index, value) in candidates {
+        if funds >= needed {
+            break;
+        }
+        selected.push(utxo_index);
+        funds += value;
+    }
+    if funds < needed {
+        return Err(Error::NotEnoughFunds);
+    }
+
+    let mut input_index = start_index;
+    for utxo_index in selected {
+        while state.inputs.contains_key(&input_index) {
+            input_index += 1;
+        }
+        add_from_utxo(state, input_index, utxo_index)?;
+    }
+
+    Ok(funds - needed)
+}
+
+/// Add inputs that spend the given UTXOs with the sequence and sighash type of the given input
+///
+/// New inputs take the free indices after the given input.
+///
+/// Returns the indices of the new inputs
+pub fn duplicate_input(
+    state: &mut State,
+    input_index: usize,
+    utxo_indices: &[usize],
+) -> Result<Vec<usize>, Error> {
+    let source = state.inputs.get(&input_index).ok_or(Error::MissingInput)?;
+    let (sequence, sighash) = (source.sequence, source.sighash);
+
+    let mut new_indices = Vec::with_capacity(utxo_indices.len());
+    let mut new_index = input_index + 1;
+    for &utxo_index in utxo_indices {
+        while state.inputs.contains_key(&new_index) {
+            new_index += 1;
+        }
+        add_from_utxo(state, new_index, utxo_index)?;
+
+        let input = state.inputs.get_mut(&new_index).expect("just added");
+        input.sequence = sequence;
+        input.sighash = sighash;
+        new_indices.push(new_index);
+    }
+
+    Ok(new_indices)
+}
+
+/// Get the address that funds the given input
+pub fn get_input_address(state: &State, input_index: usize) -> Result<bitcoin::Address, Error> {
+    let input = state.inputs.get(&input_index).ok_or(Error::MissingInput)?;
+    address::get_address(state, &input.utxo.descriptor)
+}
+
 pub fn delete_input(state: &mut State, input_index: usize) -> Result<Input, Error> {
     state.inputs.remove(&input_index).ok_or(Error::MissingInput)
 }
 
+/// Move input to another index
+///
+/// If the other index is occupied, then both inputs swap places
+pub fn move_input(state: &mut State, from: usize, to: usize) -> Result<(), Error> {
+    let input = state.inputs.remove(&from).ok_or(Error::MissingInput)?;
+
+    if let Some(occupant) = state.inputs.insert(to, input) {
+        state.inputs.insert(from, occupant);
+    }
+
+    Ok(())
+}
+
 pub fn update_sequence_height(
     state: &mut State,
     input_index: usize,
@@ -41,6 +199,53 @@ pub fn update_sequence_height(
     Ok(())
 }
 
+/// Enable relative timelock in seconds, rounded up to the next multiple of 512 seconds
+///
+/// Returns the effective relative timelock in seconds
+pub fn update_sequence_time(
+    state: &mut State,
+    input_index: usize,
+    relative_seconds: u32,
+) -> Result<u32, Error> {
+    let input = state
+        .inputs
+        .get_mut(&input_index)
+        .ok_or(Error::MissingInput)?;
+    input.sequence =
+        Sequence::from_seconds_ceil(relative_seconds).map_err(|_| Error::InvalidRelativeTime)?;
+
+    Ok((input.sequence.0 & 0xffff) * 512)
+}
+
+/// Set the sequence to the exact given value
+pub fn update_sequence_raw(
+    state: &mut State,
+    input_index: usize,
+    sequence: Sequence,
+) -> Result<(), Error> {
+    let input = state
+        .inputs
+        .get_mut(&input_index)
+        .ok_or(Error::MissingInput)?;
+    input.sequence = sequence;
+
+    Ok(())
+}
+
+pub fn update_sighash(
+    state: &mut State,
+    input_index: usize,
+    sighash: SchnorrSighashType,
+) -> Result<(), Error> {
+    let input = state
+        .inputs
+        .get_mut(&input_index)
+        .ok_or(Error::MissingInput)?;
+    input.sighash = sighash;
+
+    Ok(())
+}
+
 pub fn set_sequence_max(state: &mut State, input_index: usize) -> Result<(), Error> {
     let input = state
         .inputs
@@ -50,3 +255,117 @@ pub fn set_sequence_max(state: &mut State, input_index: usize) -> Result<(), Err
 
     Ok(())
 }
+
+/// Signal replace-by-fee without relative timelock
+pub fn enable_rbf(state: &mut State, input_index: usize) -> Result<(), Error> {
+    let input = state
+        .inputs
+        .get_mut(&input_index)
+        .ok_or(Error::MissingInput)?;
+    input.sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn state_with_utxo() -> State {
+        let descriptor = Descriptor::from_str(
+            "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)",
+        )
+        .unwrap();
+        let mut state = State::new();
+        state.utxos.push(Utxo {
+            outpoint: bitcoin::OutPoint::default(),
+            output: bitcoin::TxOut {
+                value: 100_000,
+                script_pubkey: descriptor.script_pubkey(),
+            },
+            descriptor,
+        });
+        state
+    }
+
+    /// State with UTXOs of the given values, at vouts 0, 1, ...
+    fn state_with_values(values: &[u64]) -> State {
+        let template = state_with_utxo().utxos.remove(0);
+        let mut state = State::new();
+        for (vout, value) in values.iter().enumerate() {
+            let mut utxo = template.clone();
+            utxo.outpoint.vout = vout as u32;
+            utxo.output.value = *value;
+            state.utxos.push(utxo);
+        }
+        state
+    }
+
+    fn selected_values(state: &State) -> Vec<u64> {
+        state
+            .inputs
+            .keys()
+            .sorted()
+            .map(|index| state.inputs[index].utxo.output.value)
+            .collect()
+    }
+
+    #[test]
+    fn select_largest_first() {
+        let mut state = state_with_values(&[1_000, 5_000, 3_000]);
+        let change = select_utxos(&mut state, 0, 4_000, SelectOrder::LargestFirst).unwrap();
+
+        assert_eq!(selected_values(&state), vec![5_000]);
+        assert_eq!(change, 1_000);
+    }
+
+    #[test]
+    fn select_smallest_first() {
+        let mut state = state_with_values(&[1_000, 5_000, 3_000]);
+        let change = select_utxos(&mut state, 0, 4_000, SelectOrder::SmallestFirst).unwrap();
+
+        assert_eq!(selected_values(&state), vec![1_000, 3_000]);
+        assert_eq!(change, 0);
+    }
+
+    #[test]
+    fn input_address_matches_inbound_address() {
+        let descriptor = Descriptor::from_str(
+            "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f,pk(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f))",
+        )
+        .unwrap();
+        let mut state = State::new();
+        let (index, inbound_address) = address::set_address(&mut state, descriptor).unwrap();
+        let txid = bitcoin::OutPoint::default().txid;
+        address::into_utxo(&mut state, index, txid, 0, 100_000, false).unwrap();
+        add_from_utxo(&mut state, 0, 0).unwrap();
+
+        assert_eq!(get_input_address(&state, 0).unwrap(), inbound_address);
+    }
+
+    #[test]
+    fn same_utxo_at_other_index_is_double_spend() {
+        let mut state = state_with_utxo();
+        add_from_utxo(&mut state, 0, 0).unwrap();
+        update_sequence_height(&mut state, 0, 10).unwrap();
+
+        assert!(matches!(
+            add_from_utxo(&mut state, 1, 0),
+            Err(Error::DoubleSpend)
+        ));
+        assert_eq!(state.inputs.len(), 1);
+        assert_eq!(state.inputs[&0].sequence, Sequence::from_height(10));
+    }
+
+    #[test]
+    fn same_utxo_at_same_index_replaces_input() {
+        let mut state = state_with_utxo();
+        add_from_utxo(&mut state, 0, 0).unwrap();
+        update_sequence_height(&mut state, 0, 10).unwrap();
+
+        let old = add_from_utxo(&mut state, 0, 0).unwrap();
+        assert_eq!(old.unwrap().sequence, Sequence::from_height(10));
+        assert_eq!(state.inputs[&0].sequence, Sequence::MAX);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::error::Error;
+use crate::state::{Input, Output, State, Utxo};
+use crate::util;
+use itertools::Itertools;
+use serde::Serialize;
+
+/// JSON view of the state for scripting
+///
+/// Decoupled from the state file format and free of secrets
+#[derive(Serialize)]
+pub struct StateView {
+    pub keys: Vec<KeyView>,
+    pub images: Vec<ImageView>,
+    pub inbound_addresses: Vec<InboundView>,
+    pub utxos: Vec<UtxoView>,
+    pub inputs: Vec<InputView>,
+    pub outputs: Vec<OutputView>,
+    pub tx_version: i32,
+    pub locktime: u32,
+    pub locktime_enabled: bool,
+    pub fee: u64,
+    pub feerate: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct KeyView {
+    pub xonly: String,
+    pub label: Option<String>,
+    pub enabled: bool,
+    pub watch_only: bool,
+}
+
+#[derive(Serialize)]
+pub struct ImageView {
+    pub image: String,
+    pub label: Option<String>,
+    pub enabled: bool,
+    pub watch_only: bool,
+}
+
+#[derive(Serialize)]
+pub struct UtxoView {
+    pub index: usize,
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    pub descriptor: String,
+    pub label: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct InboundView {
+    pub index: usize,
+    pub descriptor: String,
+}
+
+#[derive(Serialize)]
+pub struct InputView {
+    pub index: usize,
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    pub descriptor: String,
+    pub sequence: u32,
+    pub sighash: String,
+    /// Input spends an outpoint that is not in the UTXO set
+    pub external: bool,
+}
+
+#[derive(Serialize)]
+pub struct OutputView {
+    pub index: usize,
+    pub value: u64,
+    pub weight: u32,
+    pub pays_fee: bool,
+    pub destination: String,
+}
+
+/// JSON view of a signed transaction for scripting
+#[derive(Serialize)]
+pub struct SpendView {
+    pub hex: String,
+    pub txid: String,
+    pub vsize: usize,
+    pub weight: usize,
+    pub fee: u64,
+    pub feerate: f64,
+    /// Lowest output index that receives the remaining funds
+    pub change_output_index: Option<usize>,
+    pub change_value: Option<u64>,
+    /// Inputs with empty witness (spend --partial)
+    pub unsigned_inputs: Vec<usize>,
+}
+
+impl SpendView {
+    pub fn new(
+        state: &State,
+        tx_hex: String,
+        fee: u64,
+        unsigned_inputs: Vec<usize>,
+    ) -> Result<Self, Error> {
+        let tx = util::parse_transaction(&tx_hex)?;
+        let change = util::get_remaining_funds(state)?.into_iter().next();
+
+        Ok(Self {
+            txid: tx.txid().to_string(),
+            vsize: tx.vsize(),
+            weight: tx.weight(),
+            fee,
+            feerate: fee as f64 / tx.vsize() as f64,
+            change_output_index: change.map(|(index, _)| index),
+            change_value: change.map(|(_, value)| value),
+            unsigned_inputs,
+            hex: tx_hex,
+        })
+    }
+}
+
+impl UtxoView {
+    /// Views of all UTXOs with their index
+    pub fn list(state: &State) -> Vec<Self> {
+        state
+            .utxos
+            .iter()
+            .enumerate()
+            .map(|(index, utxo)| Self::new(index, utxo, state.utxo_labels.get(&utxo.outpoint)))
+            .collect()
+    }
+
+    pub fn new(index: usize, utxo: &Utxo, label: Option<&String>) -> Self {
+        Self {
+            index,
+            txid: utxo.outpoint.txid.to_string(),
+            vout: utxo.outpoint.vout,
+            value: utxo.output.value,
+            descriptor: utxo.descriptor.to_string(),
+            label: label.cloned(),
+        }
+    }
+}
+
+impl InputView {
+    pub fn new(index: usize, input: &Input, external: bool) -> Self {
+        Self {
+            index,
+            txid: input.utxo.outpoint.txid.to_string(),
+            vout: input.utxo.outpoint.vout,
+            value: input.utxo.output.value,
+            descriptor: input.utxo.descriptor.to_string(),
+            sequence: input.sequence.0,
+            sighash: input.sighash.to_string(),
+            external,
+        }
+    }
+}
+
+impl OutputView {
+    pub fn new(index: usize, output: &Output) -> Self {
+        Self {
+            index,
+            value: output.value,
+            weight: output.weight,
+            pays_fee: output.pays_fee,
+            destination: output.destination.to_string(),
+        }
+    }
+}
+
+impl StateView {
+    pub fn new(state: &State) -> Self {
+        let passive_keys = state.passive_keys.keys().map(|key| (key, false, false));
+        let active_keys = state.active_keys.keys().map(|key| (key, true, false));
+        let watch_keys = state.watch_keys.iter().map(|key| (key, false, true));
+        let keys = passive_keys
+            .chain(active_keys)
+            .chain(watch_keys)
+            .map(|(key, enabled, watch_only)| KeyView {
+                xonly: util::into_xonly(*key).to_string(),
+                label: state.key_labels.get(key).cloned(),
+                enabled,
+                watch_only,
+            })
+            .sorted_by(|a, b| a.xonly.cmp(&b.xonly))
+            .collect();
+
+        let passive_images = state
+            .passive_images
+            .keys()
+            .map(|image| (image, false, false));
+        let active_images = state.active_images.keys().map(|image| (image, true, false));
+        let watch_images = state.watch_images.iter().map(|image| (image, false, true));
+        let images = passive_images
+            .chain(active_images)
+            .chain(watch_images)
+            .map(|(image, enabled, watch_only)| ImageView {
+                image: image.to_string(),
+                label: state.image_labels.get(image).cloned(),
+                enabled,
+                watch_only,
+            })
+            .sorted_by(|a, b| a.image.cmp(&b.image))
+            .collect();
+
+        Self {
+            keys,
+            images,
+            inbound_addresses: state
+                .inbound_addresses
+                .keys()
+                .sorted()
+                .map(|index| InboundView {
+                    index: *index,
+                    descriptor: state.inbound_addresses[index].to_string(),
+                })
+                .collect(),
+            utxos: UtxoView::list(state),
+            inputs: state
+                .inputs
+                .keys()
+                .sorted()
+                .map(|index| {
+                    let input = &state.inputs[index];
+                    InputView::new(*index, input, state.is_external(input))
+                })
+                .collect(),
+            outputs: state
+                .outputs
+                .keys()
+                .sorted()
+                .map(|index| OutputView::new(*index, &state.outputs[index]))
+                .collect(),
+            tx_version: state.tx_version,
+            locktime: state.locktime.to_consensus_u32(),
+            locktime_enabled: state.locktime_enabled(),
+            fee: state.fee,
+            feerate: state.feerate,
+        }
+    }
+}
@@ -1,30 +1,211 @@
 use crate::error::Error;
 use crate::state::State;
+use crate::style;
 use crate::util;
+use miniscript::bitcoin::hashes::{sha256, Hash};
 use miniscript::bitcoin::secp256k1;
+use miniscript::bitcoin::util::bip32;
 use miniscript::{bitcoin, ToPublicKey};
+use rayon::prelude::*;
+
+/// Maximum number of attempts to generate a key pair that is not yet in the key store
+const MAX_ATTEMPTS: u32 = 100;
 
 pub fn generate_keys(state: &mut State, number: u32) -> Result<(), Error> {
     let secp = secp256k1::Secp256k1::new();
 
-    for _ in 0..number {
-        let (mut seckey, mut pubkey) = secp.generate_keypair(&mut secp256k1::rand::rngs::OsRng);
-        let (_, parity) = pubkey.x_only_public_key();
+    let keypairs: Vec<_> = if number < util::PARALLEL_THRESHOLD {
+        (0..number).map(|_| random_keypair(&secp)).collect()
+    } else {
+        (0..number)
+            .into_par_iter()
+            .map(|_| random_keypair(&secp))
+            .collect()
+    };
+
+    insert_fresh_keypairs(state, keypairs, || random_keypair(&secp))
+}
 
-        if parity == secp256k1::Parity::Odd {
-            seckey = seckey.negate();
-            pubkey = seckey.public_key(&secp);
+/// Insert the key pairs into the key store as disabled keys
+///
+/// A key pair that is already known is replaced by a fresh one from `random`
+fn insert_fresh_keypairs<F>(
+    state: &mut State,
+    keypairs: Vec<(bitcoin::PublicKey, bitcoin::KeyPair)>,
+    mut random: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> (bitcoin::PublicKey, bitcoin::KeyPair),
+{
+    for (public_key, keypair) in keypairs {
+        let (public_key, keypair) = if is_known(state, &public_key) {
+            generate_fresh_keypair(state, &mut random)?
+        } else {
+            (public_key, keypair)
+        };
+        println!(
+            "{}",
+            style::added(format_args!("New key: {}", util::into_xonly(public_key)))
+        );
+        state.passive_keys.insert(public_key, keypair);
+    }
+
+    Ok(())
+}
+
+/// Maximum number of hex digits of a vanity prefix
+const MAX_VANITY_DIGITS: usize = 10;
+
+/// Generate a key pair whose x-only public key starts with the given hex prefix
+///
+/// Gives up after the given number of tries
+pub fn generate_vanity_key(state: &mut State, prefix: &str, max_tries: u64) -> Result<(), Error> {
+    let prefix = prefix.to_lowercase();
+    if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidVanityPrefix);
+    }
+    if MAX_VANITY_DIGITS < prefix.len() {
+        return Err(Error::VanityPrefixTooLong(MAX_VANITY_DIGITS));
+    }
+
+    let secp = secp256k1::Secp256k1::new();
+    for _ in 0..max_tries {
+        let (public_key, keypair) = random_keypair(&secp);
+        let xonly = util::into_xonly(public_key);
+
+        if xonly.to_string().starts_with(&prefix) && !is_known(state, &public_key) {
+            println!("{}", style::added(format_args!("New key: {}", xonly)));
+            state.passive_keys.insert(public_key, keypair);
+            return Ok(());
         }
+    }
+
+    Err(Error::VanityNotFound(max_tries))
+}
+
+fn random_keypair(
+    secp: &secp256k1::Secp256k1<secp256k1::All>,
+) -> (bitcoin::PublicKey, bitcoin::KeyPair) {
+    let seckey = secp256k1::SecretKey::new(&mut secp256k1::rand::rngs::OsRng);
+    into_even_keypair(secp, seckey)
+}
 
-        let public_key = pubkey.to_public_key();
-        let keypair = seckey.keypair(&secp);
-        println!("New key: {}", util::into_xonly(public_key));
+fn generate_fresh_keypair<F>(
+    state: &State,
+    random: &mut F,
+) -> Result<(bitcoin::PublicKey, bitcoin::KeyPair), Error>
+where
+    F: FnMut() -> (bitcoin::PublicKey, bitcoin::KeyPair),
+{
+    for _ in 0..MAX_ATTEMPTS {
+        let (public_key, keypair) = random();
+
+        if !is_known(state, &public_key) {
+            return Ok((public_key, keypair));
+        }
+    }
+
+    Err(Error::DuplicateKey)
+}
+
+/// Derivation path of seed-derived keys (without the final index)
+const SEED_PATH: &str = "m/86'/1'/0'/0";
+
+/// Derive key pairs from a BIP39 mnemonic at m/86'/1'/0'/0/i for i in 0..number
+///
+/// Keys that are already known are skipped
+pub fn derive_keys(state: &mut State, mnemonic: &str, number: u32) -> Result<(), Error> {
+    let secp = secp256k1::Secp256k1::new();
+    let mnemonic = bip39::Mnemonic::parse(mnemonic).map_err(Error::InvalidMnemonic)?;
+    let seed = mnemonic.to_seed("");
+    let master = bip32::ExtendedPrivKey::new_master(state.network, &seed)?;
+    let fingerprint = master.fingerprint(&secp);
+    let base_path: bip32::DerivationPath = SEED_PATH.parse()?;
+
+    for index in 0..number {
+        let path = base_path.child(bip32::ChildNumber::from_normal_idx(index)?);
+        let child = master.derive_priv(&secp, &path)?;
+        let (public_key, keypair) = into_even_keypair(&secp, child.private_key);
+
+        if is_known(state, &public_key) {
+            println!("Known key: {}", util::into_xonly(public_key));
+            continue;
+        }
+
+        println!(
+            "{}",
+            style::added(format_args!("New key: {}", util::into_xonly(public_key)))
+        );
+        state.watch_keys.remove(&public_key);
         state.passive_keys.insert(public_key, keypair);
+        state.key_origins.insert(public_key, (fingerprint, path));
     }
 
     Ok(())
 }
 
+/// Negate the secret key if necessary, so the public key has an even y-coordinate
+fn into_even_keypair(
+    secp: &secp256k1::Secp256k1<secp256k1::All>,
+    mut seckey: secp256k1::SecretKey,
+) -> (bitcoin::PublicKey, bitcoin::KeyPair) {
+    let mut pubkey = seckey.public_key(secp);
+    let (_, parity) = pubkey.x_only_public_key();
+
+    if parity == secp256k1::Parity::Odd {
+        seckey = seckey.negate();
+        pubkey = seckey.public_key(secp);
+    }
+
+    (pubkey.to_public_key(), seckey.keypair(secp))
+}
+
+fn is_known(state: &State, public_key: &bitcoin::PublicKey) -> bool {
+    state.passive_keys.contains_key(public_key) || state.active_keys.contains_key(public_key)
+}
+
+pub fn import_key(
+    state: &mut State,
+    seckey: secp256k1::SecretKey,
+) -> Result<bitcoin::XOnlyPublicKey, Error> {
+    let secp = secp256k1::Secp256k1::new();
+    let (public_key, keypair) = into_even_keypair(&secp, seckey);
+
+    if is_known(state, &public_key) {
+        return Err(Error::KnownKey);
+    }
+
+    // A known secret key replaces a watch-only key
+    state.watch_keys.remove(&public_key);
+    state.passive_keys.insert(public_key, keypair);
+    Ok(util::into_xonly(public_key))
+}
+
+/// Watch a public key whose secret key is unknown
+///
+/// Descriptors may use the key, but it cannot sign
+pub fn watch_key(state: &mut State, pubkey: bitcoin::XOnlyPublicKey) -> Result<(), Error> {
+    let public_key = pubkey.to_public_key();
+
+    if is_known(state, &public_key) || state.watch_keys.contains(&public_key) {
+        return Err(Error::KnownKey);
+    }
+
+    state.watch_keys.insert(public_key);
+    Ok(())
+}
+
+/// Resolve an x-only public key given as hex or as label
+///
+/// Hex is tried first; only if it does not parse is the input looked up as label
+pub fn resolve_key(state: &State, key_or_label: &str) -> Result<bitcoin::XOnlyPublicKey, Error> {
+    if let Ok(pubkey) = key_or_label.parse() {
+        return Ok(pubkey);
+    }
+    let public_key = util::find_by_label(&state.key_labels, key_or_label)?;
+    Ok(util::into_xonly(public_key))
+}
+
 pub fn enable_key(state: &mut State, pubkey: bitcoin::XOnlyPublicKey) -> Result<(), Error> {
     let public_key = pubkey.to_public_key();
     let keypair = state
@@ -47,17 +228,133 @@ pub fn disable_key(state: &mut State, pubkey: bitcoin::XOnlyPublicKey) -> Result
     Ok(())
 }
 
+/// Enable all key pairs and return how many were disabled before
+pub fn enable_all_keys(state: &mut State) -> usize {
+    let number = state.passive_keys.len();
+    state.active_keys.extend(state.passive_keys.drain());
+    number
+}
+
+/// Disable all key pairs and return how many were enabled before
+pub fn disable_all_keys(state: &mut State) -> usize {
+    let number = state.active_keys.len();
+    state.passive_keys.extend(state.active_keys.drain());
+    number
+}
+
+/// Delete key pair
+///
+/// Returns the key pair, or `None` for watch-only keys
 pub fn delete_key(
     state: &mut State,
     pubkey: &bitcoin::XOnlyPublicKey,
-) -> Result<bitcoin::KeyPair, Error> {
+) -> Result<Option<bitcoin::KeyPair>, Error> {
     let public_key = pubkey.to_public_key();
 
+    state.key_origins.remove(&public_key);
+    state.key_labels.remove(&public_key);
+
     if let Some(keypair) = state.active_keys.remove(&public_key) {
-        Ok(keypair)
+        Ok(Some(keypair))
     } else if let Some(keypair) = state.passive_keys.remove(&public_key) {
-        Ok(keypair)
+        Ok(Some(keypair))
+    } else if state.watch_keys.remove(&public_key) {
+        Ok(None)
     } else {
         Err(Error::UnknownKey)
     }
 }
+
+/// Set label of key pair, or remove it if no label is given
+pub fn label_key(
+    state: &mut State,
+    pubkey: bitcoin::XOnlyPublicKey,
+    label: Option<String>,
+) -> Result<(), Error> {
+    let public_key = pubkey.to_public_key();
+
+    if !is_known(state, &public_key) && !state.watch_keys.contains(&public_key) {
+        return Err(Error::UnknownKey);
+    }
+
+    match label {
+        Some(label) => state.key_labels.insert(public_key, label),
+        None => state.key_labels.remove(&public_key),
+    };
+
+    Ok(())
+}
+
+fn message_hash(message: &str) -> secp256k1::Message {
+    let hash = sha256::Hash::hash(message.as_bytes());
+    secp256k1::Message::from_slice(&hash[..]).expect("32 bytes")
+}
+
+/// Sign SHA-256 of the message with the given key pair
+pub fn sign_message(
+    state: &State,
+    pubkey: bitcoin::XOnlyPublicKey,
+    message: &str,
+) -> Result<secp256k1::schnorr::Signature, Error> {
+    let secp = secp256k1::Secp256k1::new();
+    let public_key = pubkey.to_public_key();
+    let keypair = state
+        .active_keys
+        .get(&public_key)
+        .or_else(|| state.passive_keys.get(&public_key))
+        .ok_or(Error::UnknownKey)?;
+
+    Ok(secp.sign_schnorr(&message_hash(message), keypair))
+}
+
+pub fn verify_message(
+    pubkey: bitcoin::XOnlyPublicKey,
+    message: &str,
+    signature: &secp256k1::schnorr::Signature,
+) -> Result<(), Error> {
+    let secp = secp256k1::Secp256k1::verification_only();
+    secp.verify_schnorr(signature, &message_hash(message), &pubkey)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Key pair from a fixed secret key
+    fn fixed_keypair(byte: u8) -> (bitcoin::PublicKey, bitcoin::KeyPair) {
+        let secp = secp256k1::Secp256k1::new();
+        let seckey = secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+        into_even_keypair(&secp, seckey)
+    }
+
+    #[test]
+    fn duplicate_key_is_regenerated() {
+        let mut state = State::new();
+        let (known_key, known_keypair) = fixed_keypair(1);
+        state.active_keys.insert(known_key, known_keypair);
+
+        // The first fresh key pair collides again, the second one is new
+        let mut fresh = vec![fixed_keypair(2), fixed_keypair(1)];
+        insert_fresh_keypairs(&mut state, vec![fixed_keypair(1)], || fresh.pop().unwrap()).unwrap();
+
+        let (new_key, _) = fixed_keypair(2);
+        assert_eq!(state.active_keys.len(), 1);
+        assert_eq!(state.active_keys[&known_key], known_keypair);
+        assert!(!state.passive_keys.contains_key(&known_key));
+        assert!(state.passive_keys.contains_key(&new_key));
+    }
+
+    #[test]
+    fn endless_duplicates_fail_without_changes() {
+        let mut state = State::new();
+        let (known_key, known_keypair) = fixed_keypair(1);
+        state.passive_keys.insert(known_key, known_keypair);
+
+        let result = insert_fresh_keypairs(&mut state, vec![fixed_keypair(1)], || fixed_keypair(1));
+
+        assert!(matches!(result, Err(Error::DuplicateKey)));
+        assert_eq!(state.passive_keys.len(), 1);
+        assert_eq!(state.passive_keys[&known_key], known_keypair);
+    }
+}
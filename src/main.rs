@@ -1,40 +1,92 @@
+use crate::config::Config;
 use crate::error::Error;
 use crate::state::State;
 use clap::{Parser, Subcommand};
+use itertools::Itertools;
 use miniscript::bitcoin;
 use miniscript::bitcoin::hashes::sha256;
-use miniscript::bitcoin::locktime::Height;
-use miniscript::Descriptor;
+use miniscript::bitcoin::locktime::{Height, Time};
+use miniscript::bitcoin::LockTime;
+use miniscript::{Descriptor, Miniscript, Tap};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 mod address;
+mod backup;
+mod check;
+mod config;
+mod descriptor;
+mod diff;
 mod error;
 mod image;
 mod input;
+mod json;
 mod key;
+mod merge;
 mod output;
+mod pending;
+mod rpc;
 mod spend;
 mod state;
+mod style;
+mod template;
+#[cfg(test)]
+mod test_util;
 mod transaction;
 mod util;
 mod utxo;
 
-const STATE_FILE_NAME: &str = "state.json";
-
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Path of the state file
+    #[arg(long, global = true, env = "TAPPY_STATE", default_value = "state.json")]
+    state_file: PathBuf,
+    /// Do not back up the state file before changing it
+    #[arg(long, global = true)]
+    no_backup: bool,
+    /// When to color the output
+    #[arg(long, global = true, value_enum, default_value_t = style::ColorChoice::Auto)]
+    color: style::ColorChoice,
+    /// Config file with defaults [default: tappy.toml if it exists]
+    #[arg(long, global = true, env = "TAPPY_CONFIG")]
+    config: Option<PathBuf>,
+    /// Cookie file for authenticating with Bitcoin Core
+    #[arg(long, global = true, env = "TAPPY_RPC_COOKIE")]
+    rpc_cookie: Option<PathBuf>,
+    /// Address of Bitcoin Core (host or host:port)
+    #[arg(long, global = true, env = "TAPPY_RPC_CONNECT")]
+    rpc_connect: Option<String>,
+    /// Delete without asking for confirmation
+    #[arg(long, short, global = true)]
+    yes: bool,
+    /// Print each change of the state file as "before → after"
+    #[arg(long, short, global = true)]
+    verbose: bool,
     #[command(subcommand)]
     command: Command,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     /// Create empty state
     ///
     /// Fails if file already exists
     Init,
     /// Print current state
-    Print,
+    Print {
+        /// Print as JSON (without secrets)
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print short summary of current state
+    Status {
+        /// Print summary on a single line (for shell prompts)
+        #[arg(long)]
+        oneline: bool,
+    },
     /// Schnorr key pair
     Key {
         #[command(subcommand)]
@@ -45,6 +97,16 @@ enum Command {
         #[command(subcommand)]
         img_command: ImgCommand,
     },
+    /// Taproot descriptor
+    Descriptor {
+        #[clap(subcommand)]
+        descriptor_command: DescriptorCommand,
+    },
+    /// Descriptor template with placeholders
+    Template {
+        #[clap(subcommand)]
+        template_command: TemplateCommand,
+    },
     /// Temporary inbound address for creating UTXOs
     Addr {
         #[clap(subcommand)]
@@ -69,7 +131,30 @@ enum Command {
         #[clap(subcommand)]
         out_command: OutCommand,
     },
+    /// Update network for addresses and WIFs
+    ///
+    /// Without argument, print the current network
+    Network {
+        /// Network (bitcoin, testnet, signet or regtest)
+        network: Option<bitcoin::Network>,
+    },
+    /// Current transaction as a whole
+    Tx {
+        #[clap(subcommand)]
+        tx_command: TxCommand,
+    },
+    /// Update transaction version
+    ///
+    /// Without argument, print the current version
+    Version {
+        /// Transaction version (relative locktimes require at least 2)
+        #[arg(allow_negative_numbers = true)]
+        version: Option<i32>,
+    },
     /// Update locktime
+    ///
+    /// Without argument, print the current locktime
+    #[command(args_conflicts_with_subcommands = true)]
     Locktime {
         /// Absolute block height
         ///
@@ -81,15 +166,136 @@ enum Command {
         /// (which may be zero)!
         ///
         /// Other ways to enable locktime are not supported
-        height: Height,
+        height: Option<Height>,
+        #[clap(subcommand)]
+        locktime_command: Option<LocktimeCommand>,
     },
     /// Update transaction fee
+    #[command(args_conflicts_with_subcommands = true)]
     Fee {
         /// Transaction fee in satoshi
-        value: u64,
+        value: Option<u64>,
+        /// Subtract the fee from the value of this output
+        /// instead of paying it from the inputs
+        #[arg(long, value_name = "INDEX")]
+        from_output: Option<usize>,
+        /// Pay the fee from the inputs again
+        #[arg(long, conflicts_with = "from_output")]
+        from_inputs: bool,
+        #[clap(subcommand)]
+        fee_command: Option<FeeCommand>,
+    },
+    /// Set a target feerate that determines the fee during spending
+    ///
+    /// Setting an absolute fee removes the target
+    ///
+    /// Without argument, print the current target
+    Feerate {
+        /// Target feerate in sat / vB
+        #[arg(value_parser = parse_feerate)]
+        feerate: Option<f64>,
+    },
+    /// Replace the transaction by one that spends a UTXO entirely to a descriptor
+    ///
+    /// The single output receives the UTXO value minus the fee
+    Sweep {
+        /// UTXO index
+        utxo_index: usize,
+        /// Descriptor of the output
+        #[arg(value_parser = parse_descriptor)]
+        descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+        /// Set the fee from this feerate in sat / vB and the estimated size
+        #[arg(long, value_parser = parse_feerate)]
+        feerate: Option<f64>,
+        /// Discard the current inputs and outputs
+        #[arg(long)]
+        force: bool,
+    },
+    /// Replace a spent transaction by one that pays a higher feerate (RBF)
+    ///
+    /// Rebuilds the transaction from the last spend or the history,
+    /// signals RBF on every input and lets the change output pay the fee increase.
+    /// The finalization of the replaced transaction is reverted
+    RbfReplace {
+        /// Txid of the transaction to replace
+        txid: bitcoin::Txid,
+        /// Feerate of the replacement in sat / vB
+        #[arg(value_parser = parse_feerate)]
+        feerate: f64,
+        /// Output that receives the change [default: the only output to a descriptor]
+        #[arg(long, value_name = "INDEX")]
+        change: Option<usize>,
+        /// Discard the current inputs and outputs
+        #[arg(long)]
+        force: bool,
     },
+    /// Estimate transaction size and feerate without signing
+    ///
+    /// Witness sizes are worst-case estimates
+    Estimate,
+    /// Run the checks that spend would run on the transaction, without signing or saving
+    Check,
     /// Create transaction witness and print raw transaction hex to send via Bitcoin Core
-    Spend,
+    Spend {
+        /// Reorder outputs before spending (comma-separated list of current output indices)
+        ///
+        /// The first listed output becomes output 0, the second becomes output 1, and so on
+        ///
+        /// Every output index must be listed exactly once
+        #[arg(long, value_delimiter = ',')]
+        output_order: Option<Vec<usize>>,
+        /// Broadcast the transaction via Bitcoin Core instead of printing it
+        #[arg(long)]
+        broadcast: bool,
+        /// Finalize the transaction after broadcasting it
+        #[arg(long, requires = "broadcast")]
+        finalize: bool,
+        /// Print the unsigned transaction as PSBT (base64) for co-signers
+        #[arg(long, conflicts_with = "broadcast")]
+        psbt: bool,
+        /// Sign the given PSBT (base64) with the enabled keys and print it
+        ///
+        /// Ignores the transaction of the state
+        #[arg(long, conflicts_with_all = ["output_order", "broadcast", "psbt"])]
+        from_psbt: Option<bitcoin::psbt::Psbt>,
+        /// Print the transaction without saving the state
+        #[arg(long, conflicts_with = "broadcast")]
+        dry_run: bool,
+        /// Skip checking the witnesses locally before printing the transaction
+        #[arg(long)]
+        skip_check: bool,
+        /// Allow input funds that no output receives to go to the fee
+        ///
+        /// Without this flag, spending fails if there is no output for the remaining funds
+        /// and the inputs pay more than the outputs plus the fee
+        #[arg(long)]
+        burn_excess: bool,
+        /// Set the fee from the feerate that Bitcoin Core estimates for confirmation
+        /// within the given number of blocks
+        ///
+        /// Takes precedence over the feerate target of the state
+        #[arg(long, value_name = "CONF_TARGET", conflicts_with = "from_psbt")]
+        estimate_fee: Option<u16>,
+        /// Feerate in sat / vB to use if Bitcoin Core has no estimate [default: 1]
+        #[arg(long, value_parser = parse_feerate, requires = "estimate_fee")]
+        fallback_feerate: Option<f64>,
+        /// Print the witness items of each input with their sizes before the transaction
+        #[arg(long, conflicts_with = "psbt")]
+        dump_witness: bool,
+        /// Leave inputs that cannot be satisfied unsigned instead of failing
+        ///
+        /// Their witnesses stay empty for the other signers to fill in
+        #[arg(long, conflicts_with_all = ["psbt", "broadcast"])]
+        partial: bool,
+        /// Print the transaction with txid, size, fee and change as JSON
+        #[arg(long, conflicts_with_all = ["psbt", "broadcast", "dump_witness"])]
+        json: bool,
+    },
+    /// Decode and print a raw transaction
+    Decode {
+        /// Raw transaction (hex)
+        tx_hex: String,
+    },
     /// Finalize transaction and save transaction outputs as UTXOs
     ///
     /// Creates new transaction with first transaction output as input
@@ -97,12 +303,85 @@ enum Command {
     /// Removes transaction inputs from UTXO set
     Final {
         /// Transaction id (hex)
-        txid: bitcoin::Txid,
+        ///
+        /// Checked against the txid of the last spent transaction
+        txid: Option<bitcoin::Txid>,
+    },
+    /// Rebuild UTXO set from the history of finalized transactions
+    ///
+    /// Reports UTXOs that are restored or removed in the process.
+    /// UTXOs that the history never created are kept.
+    RebuildUtxos {
+        /// Also remove UTXOs that the history never created
+        #[arg(long)]
+        force: bool,
+    },
+    /// Merge keys, images and UTXOs of another state file
+    ///
+    /// Known items are skipped and merged keys and images are disabled.
+    /// The transaction of the other state file is ignored.
+    ImportState {
+        /// Path of the other state file
+        path: PathBuf,
+    },
+    /// Revert the last command that changed the state
+    ///
+    /// Undoing again reverts the undo
+    Undo,
+    /// Backups of the state file
+    Backup {
+        #[command(subcommand)]
+        backup_command: BackupCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupCommand {
+    /// List backups with their timestamps and sizes, oldest first
+    List,
+    /// Delete all but the newest backups
+    Prune {
+        /// Number of backups to keep
+        #[arg(long)]
+        keep: usize,
+    },
+    /// Replace the state file by a backup
+    ///
+    /// The current state can be brought back via undo
+    Restore {
+        /// Unix timestamp of the backup
+        timestamp: u64,
     },
 }
 
 #[derive(Subcommand)]
 enum KeyCommand {
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(KeyEditCommand),
+    /// Sign a message with a key pair
+    ///
+    /// The message is hashed with SHA-256 of its UTF-8 bytes
+    /// and signed with a BIP340 Schnorr signature
+    Sign {
+        /// X-only public key
+        key: bitcoin::XOnlyPublicKey,
+        /// Message
+        message: String,
+    },
+    /// Verify a message signature
+    Verify {
+        /// X-only public key
+        key: bitcoin::XOnlyPublicKey,
+        /// Message
+        message: String,
+        /// Schnorr signature (hex)
+        signature: bitcoin::secp256k1::schnorr::Signature,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyEditCommand {
     /// Generate key pairs
     ///
     /// Public keys are guaranteed to have an even y-coordinate (to work as x-only public keys)
@@ -110,109 +389,590 @@ enum KeyCommand {
         /// Number of pairs
         number: u32,
     },
-    /// Enable key pair
-    En {
+    /// Generate key pair whose x-only public key starts with the given hex prefix
+    ///
+    /// Each additional hex digit makes the search 16 times longer
+    GenVanity {
+        /// Hex prefix (at most 10 digits)
+        prefix: String,
+        /// Maximum number of key pairs to try
+        #[arg(long, default_value = "1000000")]
+        max_tries: u64,
+    },
+    /// Derive key pairs from BIP39 mnemonic
+    ///
+    /// Keys are derived at m/86'/1'/0'/0/i for i = 0, 1, ..., number - 1
+    ///
+    /// Public keys are guaranteed to have an even y-coordinate (to work as x-only public keys)
+    Seed {
+        /// BIP39 mnemonic (quoted)
+        mnemonic: String,
+        /// Number of pairs
+        number: u32,
+    },
+    /// Import key pair from private key
+    ///
+    /// The private key is negated if necessary,
+    /// so the public key has an even y-coordinate (to work as x-only public key)
+    Import {
+        /// Private key (WIF) of any network
+        private_key: bitcoin::PrivateKey,
+    },
+    /// Import key pair from raw secret key
+    ///
+    /// The secret key is negated if necessary,
+    /// so the public key has an even y-coordinate (to work as x-only public key)
+    FromSecret {
+        /// Secret key (64 hex digits)
+        #[arg(value_parser = parse_secret_key)]
+        secret_key: bitcoin::secp256k1::SecretKey,
+    },
+    /// Watch public key whose secret key is unknown
+    ///
+    /// Descriptors may use the key, but it cannot sign
+    Watch {
         /// X-only public key
         key: bitcoin::XOnlyPublicKey,
     },
-    /// Disable key pair
+    /// Enable key pairs
+    En {
+        /// X-only public keys or their labels
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// Enable all key pairs
+    EnAll,
+    /// Disable all key pairs
+    DisAll,
+    /// Disable key pairs
     Dis {
-        /// X-only public key
-        key: bitcoin::XOnlyPublicKey,
+        /// X-only public keys or their labels
+        #[arg(required = true)]
+        keys: Vec<String>,
     },
-    /// Delete key pair
+    /// Delete key pairs
     Del {
+        /// X-only public keys or their labels
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// Label key pair
+    Label {
         /// X-only public key
         key: bitcoin::XOnlyPublicKey,
+        /// Human-readable label
+        ///
+        /// Omit to remove the label
+        label: Option<String>,
     },
 }
 
 #[derive(Subcommand)]
 enum ImgCommand {
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(ImgEditCommand),
+    /// Print the digests of a preimage for every hash type
+    ///
+    /// Descriptors may use hash256, ripemd160 and hash160 locks with these digests
+    Digests {
+        /// SHA-256 image
+        image: sha256::Hash,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImgEditCommand {
     /// Generate (pre)image pairs
     Gen {
         /// Number of pairs
         number: u32,
     },
-    /// Enable (pre)image pair
-    En {
+    /// Derive (pre)image pair from an enabled key pair
+    ///
+    /// The preimage is SHA-256(secret key || index),
+    /// where the index is encoded as 4 bytes big-endian
+    Derive {
+        /// X-only public key
+        key: bitcoin::XOnlyPublicKey,
+        /// Derivation index
+        index: u32,
+    },
+    /// Import (pre)image pair from a known preimage
+    Import {
+        /// 32-byte preimage (hex)
+        preimage: String,
+    },
+    /// Watch image whose preimage is unknown
+    ///
+    /// Descriptors may use the image, but its hashlock cannot be satisfied
+    Watch {
         /// SHA-256 image
         image: sha256::Hash,
     },
-    /// Disable (pre)image pair
+    /// Enable (pre)image pairs
+    En {
+        /// SHA-256 images or their labels
+        #[arg(required = true)]
+        images: Vec<String>,
+    },
+    /// Enable all (pre)image pairs
+    EnAll,
+    /// Disable all (pre)image pairs
+    DisAll,
+    /// Disable (pre)image pairs
     Dis {
-        /// SHA-256 image
-        image: sha256::Hash,
+        /// SHA-256 images or their labels
+        #[arg(required = true)]
+        images: Vec<String>,
     },
-    /// Delete (pre)image pair
+    /// Delete (pre)image pairs
     Del {
+        /// SHA-256 images or their labels
+        #[arg(required = true)]
+        images: Vec<String>,
+    },
+    /// Label (pre)image pair
+    Label {
         /// SHA-256 image
         image: sha256::Hash,
+        /// Human-readable label
+        ///
+        /// Omit to remove the label
+        label: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum DescriptorCommand {
+    /// Compare two descriptors by internal key, leaves, keys, images and timelocks
+    Diff {
+        /// Old descriptor
+        #[arg(value_parser = parse_descriptor)]
+        first: Descriptor<bitcoin::XOnlyPublicKey>,
+        /// New descriptor
+        #[arg(value_parser = parse_descriptor)]
+        second: Descriptor<bitcoin::XOnlyPublicKey>,
+    },
+    /// Print Taproot internals: merkle root, output key, script pubkey and leaf scripts
+    Info {
+        /// Descriptor
+        #[arg(value_parser = parse_descriptor)]
+        descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    },
+    /// Print the taproot descriptor with leaves at the given depths
+    ///
+    /// Put likely spend paths closer to the root for smaller witnesses
+    Tree {
+        /// Internal key (x-only, hex)
+        internal_key: bitcoin::XOnlyPublicKey,
+        /// Leaves as DEPTH:MINISCRIPT in depth-first order, from left to right
+        ///
+        /// Depths must form a complete binary tree, such as 1, 2, 2
+        #[arg(value_parser = parse_leaf)]
+        leaves: Vec<(u8, Miniscript<bitcoin::XOnlyPublicKey, Tap>)>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TemplateCommand {
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(TemplateEditCommand),
+    /// Print the descriptor that results from filling in the placeholders
+    ///
+    /// Use the output wherever a descriptor is accepted, such as `addr set` or `out new`
+    Use {
+        /// Template name
+        name: String,
+        /// Values for the placeholders $0, $1, ... in order
+        arguments: Vec<String>,
+    },
+    /// List saved templates
+    List,
+}
+
+#[derive(Subcommand)]
+enum TemplateEditCommand {
+    /// Save descriptor template under a name
+    Save {
+        /// Template name
+        name: String,
+        /// Descriptor with placeholders $0, $1, ... for keys and images
+        template: String,
+    },
+    /// Delete template
+    Del {
+        /// Template name
+        name: String,
     },
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum AddrCommand {
-    /// Set inbound address to fund via Bitcoin Core
-    Set {
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(AddrEditCommand),
+    /// Print address as QR code for scanning with a mobile wallet
+    Qr {
+        /// Inbound address index (defaults to the lowest index)
+        #[arg(conflicts_with = "descriptor")]
+        index: Option<usize>,
+        /// Descriptor to show instead of an inbound address
+        #[arg(long, value_parser = parse_descriptor)]
+        descriptor: Option<Descriptor<bitcoin::XOnlyPublicKey>>,
+    },
+    /// Show address of descriptor without adding it as inbound address
+    Show {
+        /// Descriptor
+        #[arg(value_parser = parse_descriptor)]
+        descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    },
+    /// Print the worst-case witness weight of each spend path of a descriptor
+    ///
+    /// Helps to place cheap common paths close to the root
+    Weights {
+        /// Descriptor
+        #[arg(value_parser = parse_descriptor)]
+        descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    },
+    /// Check that an address is the address of a descriptor
+    ///
+    /// Fails if they do not match
+    Verify {
+        /// Address
+        address: bitcoin::Address,
         /// Descriptor
+        #[arg(value_parser = parse_descriptor)]
         descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
     },
+    /// Print the Bitcoin Core command that funds an inbound address
+    FundCmd {
+        /// Amount in satoshi (or with unit: 0.001btc, 100000sat)
+        #[arg(value_parser = parse_amount)]
+        value: u64,
+        /// Inbound address index (defaults to the lowest index)
+        index: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum AddrEditCommand {
+    /// Add inbound address to fund via Bitcoin Core
+    Set {
+        /// Descriptor
+        #[arg(required_unless_present = "file", value_parser = parse_descriptor)]
+        descriptor: Option<Descriptor<bitcoin::XOnlyPublicKey>>,
+        /// Read the descriptor from a file instead
+        #[arg(long, conflicts_with = "descriptor")]
+        file: Option<PathBuf>,
+    },
+    /// Add inbound address that is spent by the key path of a key only
+    ///
+    /// Shorthand for addr set "tr(KEY)"
+    Keypath {
+        /// X-only public key or its label
+        key: String,
+    },
+    /// Delete inbound address
+    Del {
+        /// Inbound address index
+        index: usize,
+    },
+    /// Find UTXOs of inbound addresses via Bitcoin Core
+    ///
+    /// Scans the UTXO set of the node (scantxoutset)
+    Scan,
     /// Convert inbound address into UTXO
     Utxo {
+        /// Inbound address index
+        index: usize,
         /// UTXO transaction id (hex)
-        txid: bitcoin::Txid,
+        #[arg(required_unless_present = "from_core")]
+        txid: Option<bitcoin::Txid>,
         /// Output index (vout)
-        output_index: u32,
-        /// Output value in satoshi
-        value: u64,
+        #[arg(required_unless_present = "from_core")]
+        output_index: Option<u32>,
+        /// Output value in satoshi (or with unit: 0.001btc, 100000sat)
+        #[arg(value_parser = parse_amount, required_unless_present = "from_core")]
+        value: Option<u64>,
+        /// Take txid, output index and value from the funding transaction
+        /// as JSON of gettransaction or getrawtransaction <txid> true
+        #[arg(long, value_name = "JSON", conflicts_with_all = ["txid", "output_index", "value"])]
+        from_core: Option<String>,
+        /// Check via Bitcoin Core that the output pays the value to the inbound address
+        #[arg(long)]
+        verify: bool,
     },
 }
 
+#[derive(Subcommand)]
+enum TxCommand {
+    /// Remove all inputs and outputs and reset fee and locktime
+    ///
+    /// Keys, images and UTXOs stay untouched
+    Clear,
+}
+
 #[derive(Subcommand)]
 enum UtxoCommand {
     /// List UTXOs with their index
-    List,
+    List {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(UtxoEditCommand),
+}
+
+#[derive(Subcommand)]
+enum UtxoEditCommand {
     /// Delete UTXO
     Del {
         /// UTXO index
         utxo_index: usize,
     },
+    /// Label UTXO
+    Label {
+        /// UTXO index
+        utxo_index: usize,
+        /// Human-readable label
+        ///
+        /// Omit to remove the label
+        label: Option<String>,
+    },
+    /// Import UTXOs from the outputs of a raw transaction
+    ///
+    /// Outputs are matched against the given descriptors and the inbound addresses
+    FromTx {
+        /// Raw transaction (hex)
+        tx_hex: String,
+        /// Descriptors of the outputs to import
+        #[arg(value_parser = parse_descriptor)]
+        descriptors: Vec<Descriptor<bitcoin::XOnlyPublicKey>>,
+    },
+    /// Import UTXOs from a JSON file
+    ///
+    /// The file holds an array of objects with the fields
+    /// descriptor, txid, vout and value (in satoshi)
+    ///
+    /// UTXOs that are already known are skipped
+    Import {
+        /// Path to the JSON file
+        path: PathBuf,
+        /// Abort at the first invalid entry instead of skipping it
+        #[arg(long)]
+        strict: bool,
+    },
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum InCommand {
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(InEditCommand),
+    /// Show address that funds transaction input
+    Addr,
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum InEditCommand {
     /// Add new transaction input
     New {
         /// UTXO index
         utxo_index: usize,
     },
+    /// Add new transaction input that spends an outpoint outside the UTXO set
+    Raw {
+        /// Transaction id (hex)
+        txid: bitcoin::Txid,
+        /// Output index (vout)
+        vout: u32,
+        /// Output value in satoshi (or with unit: 0.001btc, 100000sat)
+        #[arg(value_parser = parse_amount)]
+        value: u64,
+        /// Descriptor of the output
+        #[arg(value_parser = parse_descriptor)]
+        descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    },
+    /// Select UTXOs that cover the target amount plus the fee
+    ///
+    /// Selected UTXOs become inputs at the free indices starting at this index
+    Select {
+        /// Target amount in satoshi (or with unit: 0.001btc, 100000sat)
+        #[arg(value_parser = parse_amount)]
+        target: u64,
+        /// Take the largest UTXOs first (default)
+        #[arg(long, conflicts_with = "smallest_first")]
+        largest_first: bool,
+        /// Take the smallest UTXOs first
+        #[arg(long)]
+        smallest_first: bool,
+    },
+    /// Add inputs that spend other UTXOs with the sequence and sighash type of this input
+    ///
+    /// New inputs take the free indices after this index
+    Dup {
+        /// UTXO indices (one new input each)
+        #[arg(required = true)]
+        utxo_indices: Vec<usize>,
+    },
     /// Delete transaction input
     Del,
+    /// Move transaction input to another index
+    ///
+    /// Swaps with the input at that index, if there is one
+    Move {
+        /// New input index
+        to: usize,
+    },
     /// Update sequence of transaction input
     Seq {
         #[clap(subcommand)]
         seq_command: SeqCommand,
     },
+    /// Update sighash type of transaction input
+    Sighash {
+        /// Sighash type: default, all, none, single,
+        /// all-anyonecanpay, none-anyonecanpay or single-anyonecanpay
+        #[arg(value_parser = parse_sighash)]
+        sighash: bitcoin::SchnorrSighashType,
+    },
+    /// Signal replace-by-fee (sequence 0xfffffffd)
+    ///
+    /// Replaces any relative locktime of this input
+    Rbf,
+    /// Stop signaling replace-by-fee (sequence 0xffffffff)
+    RbfOff,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum OutCommand {
+    // Subcommands that change the state file
+    #[command(flatten)]
+    Edit(OutEditCommand),
+    /// Show destination, script pubkey, address and final value of transaction output
+    Show,
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum OutEditCommand {
     /// Add new transaction output
     New {
-        /// Descriptor
-        descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
-        /// Output value in satoshi
+        /// Descriptor (output value if the descriptor is read from a file)
+        #[arg(required_unless_present = "file")]
+        descriptor: Option<String>,
+        /// Output value in satoshi (or with unit: 0.001btc, 100000sat)
         ///
         /// Zero satoshi means that the output will receive the remaining input funds
         /// (inputs minus outputs minus fee)
         ///
-        /// This is possible for at most one input!
-        #[arg(default_value_t = 0)]
+        /// Several such outputs split the remaining funds according to their weights
+        #[arg(conflicts_with = "file", value_parser = parse_amount)]
+        value: Option<u64>,
+        /// Read the descriptor from a file instead
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Share of the remaining funds, if the value is zero
+        #[arg(long, default_value = "1")]
+        weight: u32,
+    },
+    /// Add new transaction output that pays to an address
+    ///
+    /// Outputs to addresses are not converted into UTXOs during finalization
+    #[command(alias = "address")]
+    Pay {
+        /// Address
+        address: bitcoin::Address,
+        /// Output value in satoshi (or with unit: 0.001btc, 100000sat)
+        ///
+        /// Zero satoshi means that the output will receive the remaining input funds
+        /// (inputs minus outputs minus fee)
+        ///
+        /// Several such outputs split the remaining funds according to their weights
+        #[arg(default_value = "0", value_parser = parse_amount)]
+        value: u64,
+        /// Share of the remaining funds, if the value is zero
+        #[arg(long, default_value = "1")]
+        weight: u32,
+    },
+    /// Add new transaction output that pays to a raw script pubkey
+    ///
+    /// Outputs to raw scripts are not converted into UTXOs during finalization
+    Script {
+        /// Script pubkey (hex)
+        script_pubkey: String,
+        /// Output value in satoshi (or with unit: 0.001btc, 100000sat)
+        ///
+        /// Zero satoshi means that the output will receive the remaining input funds
+        /// (inputs minus outputs minus fee)
+        ///
+        /// Several such outputs split the remaining funds according to their weights
+        #[arg(default_value = "0", value_parser = parse_amount)]
         value: u64,
+        /// Share of the remaining funds, if the value is zero
+        #[arg(long, default_value = "1")]
+        weight: u32,
+    },
+    /// Add new OP_RETURN output with zero value
+    Data {
+        /// Data (hex, at most 80 bytes)
+        data: String,
+    },
+    /// Copy transaction output into the next free indices
+    Dup {
+        /// Number of copies
+        count: usize,
     },
     /// Delete transaction output
     Del,
+    /// Move transaction output to another index
+    ///
+    /// Swaps with the output at that index, if there is one
+    Move {
+        /// New output index
+        to: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum LocktimeCommand {
+    /// Set locktime to a unix timestamp
+    ///
+    /// A transaction is valid if the median time of the last 11 blocks
+    /// before its containing block is greater than its locktime
+    Time {
+        /// Unix timestamp (at least 500000000)
+        #[arg(value_parser = parse_timestamp)]
+        timestamp: Time,
+    },
+    /// Reset locktime to zero
+    Off,
+}
+
+#[derive(Subcommand)]
+enum FeeCommand {
+    /// Estimate total fees for a chain of transactions
+    ///
+    /// The chain starts with the current transaction
+    ///
+    /// Each following transaction spends the first output of its parent
+    /// and pays the remaining value to the same descriptor
+    ChainEstimate {
+        /// Number of transactions in the chain
+        depth: u32,
+        /// Target feerate in sat / vB
+        feerate: f64,
+    },
 }
 
 #[derive(Subcommand)]
@@ -228,123 +988,792 @@ enum SeqCommand {
         #[arg(default_value_t = 0)]
         relative_height: u16,
     },
+    /// Enable time-based relative locktime for this input
+    EnableTime {
+        /// Relative time in seconds
+        ///
+        /// Rounded up to the next multiple of 512 seconds
+        relative_seconds: u32,
+    },
     /// Disable relative locktime for this input
     Disable,
+    /// Set the sequence of this input to an arbitrary value
+    ///
+    /// The value is stored without interpretation
+    Raw {
+        /// Sequence (decimal or hex with 0x prefix)
+        #[arg(value_parser = parse_sequence)]
+        sequence: u32,
+    },
 }
 
-fn main() -> Result<(), Error> {
-    let cli = Cli::parse();
+/// Ask a yes/no question, unless confirmed beforehand
+///
+/// Answers yes without asking if stdin is not a terminal
+fn confirm(question: std::fmt::Arguments, yes: bool) -> Result<bool, Error> {
+    if yes || !std::io::stdin().is_terminal() {
+        return Ok(true);
+    }
 
-    match cli.command {
-        Command::Init => {
-            let state = State::new();
-            println!("Generating state.json");
-            state.save(STATE_FILE_NAME, true)?;
-        }
-        Command::Print => {
-            let state = State::load(STATE_FILE_NAME)?;
-            println!("{}", state);
-        }
-        Command::Key { key_command } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
 
-            match key_command {
-                KeyCommand::Gen { number } => {
-                    key::generate_keys(&mut state, number)?;
-                }
-                KeyCommand::En { key } => {
-                    key::enable_key(&mut state, key)?;
-                    println!("Enabling key: {}", key);
-                }
-                KeyCommand::Dis { key } => {
-                    key::disable_key(&mut state, key)?;
-                    println!("Disabling key: {}", key);
-                }
-                KeyCommand::Del { key } => {
-                    let old = key::delete_key(&mut state, &key)?;
-                    println!("Deleting key pair: {}", old.display_secret());
-                }
-            }
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
 
-            state.save(STATE_FILE_NAME, false)?;
-        }
-        Command::Img { img_command } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+/// Ask whether to proceed with deleting, unless confirmed beforehand
+///
+/// Proceeds without asking if stdin is not a terminal
+fn confirm_delete(what: std::fmt::Arguments, yes: bool) -> Result<(), Error> {
+    match confirm(format_args!("Delete {}? Are you sure?", what), yes)? {
+        true => Ok(()),
+        false => Err(Error::Aborted),
+    }
+}
 
-            match img_command {
-                ImgCommand::Gen { number } => {
-                    image::generate_images(&mut state, number)?;
-                }
-                ImgCommand::En { image } => {
-                    image::enable_image(&mut state, image)?;
-                    println!("Enabling image: {}", image);
-                }
-                ImgCommand::Dis { image } => {
-                    image::disable_image(&mut state, image)?;
-                    println!("Disabling image: {}", image);
-                }
-                ImgCommand::Del { image } => {
-                    let old = image::delete_image(&mut state, &image)?;
-                    print!("Deleting (pre)image pair: ");
-                    for byte in old {
-                        print!("{:02x}", byte);
-                    }
-                    println!();
-                }
-            }
+/// Finalize a transaction that was broadcast by a spend that stopped before saving the state
+///
+/// The node is asked whether it knows the transaction.
+/// If the node does not answer, the marker is kept for the next run
+fn recover_pending_broadcast(
+    state_file: &Path,
+    backup: bool,
+    yes: bool,
+    verbose: bool,
+) -> Result<(), Error> {
+    let spend = match pending::load(state_file)? {
+        Some(spend) => spend,
+        None => return Ok(()),
+    };
+    let txid = spend.transaction.txid();
+    let mut state = State::load(state_file)?;
+
+    // The spend stopped after saving the state
+    if state.history.iter().any(|entry| entry.txid == txid) {
+        return pending::clear(state_file);
+    }
 
-            state.save(STATE_FILE_NAME, false)?;
+    match rpc::get_transaction(txid) {
+        Ok(_) => {}
+        Err(Error::Rpc(message)) if message.contains(rpc::UNKNOWN_TRANSACTION) => {
+            println!(
+                "{}",
+                style::removed(format_args!(
+                    "Discarding pending broadcast of {}: the node does not know it",
+                    txid
+                ))
+            );
+            return pending::clear(state_file);
         }
-        Command::Addr { addr_command } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+        Err(error) => {
+            println!(
+                "{}",
+                style::error(format_args!(
+                    "Warning: Cannot check pending broadcast of {}: {}",
+                    txid, error
+                ))
+            );
+            return Ok(());
+        }
+    }
 
-            match addr_command {
-                AddrCommand::Set { descriptor } => {
-                    let address = address::set_address(&mut state, descriptor)?;
-                    println!("Fund this address: {}", address);
+    state.last_spend = Some(spend);
+    // Without a terminal, finalize only if confirmed beforehand
+    if !yes && !std::io::stdin().is_terminal() {
+        println!(
+            "{}",
+            style::error(format_args!(
+                "Transaction {} was broadcast but not finalized; pass --yes to finalize it",
+                txid
+            ))
+        );
+        return Ok(());
+    }
+
+    let question = format_args!(
+        "Transaction {} was broadcast but not finalized. Finalize it?",
+        txid
+    );
+    if confirm(question, yes)? {
+        transaction::finalize_transaction(&mut state, Some(txid))?;
+    } else {
+        println!(
+            "Remembered as the last spend; finalize it by `tappy final {}`",
+            txid
+        );
+    }
+    save_state(&state, state_file, backup, verbose)?;
+    pending::clear(state_file)
+}
+
+/// Resolve every hex value or label, or return the first error
+fn resolve_all<T, F>(names: &[String], resolve: F) -> Result<Vec<T>, Error>
+where
+    F: Fn(&str) -> Result<T, Error>,
+{
+    names.iter().map(|name| resolve(name)).collect()
+}
+
+/// Apply the action to each item, reporting errors per item
+///
+/// Returns the first error after all items have been tried
+fn for_each_item<T, F>(items: &[T], mut action: F) -> Result<(), Error>
+where
+    T: Copy + std::fmt::Display,
+    F: FnMut(T) -> Result<(), Error>,
+{
+    let mut first_error = None;
+
+    for item in items {
+        if let Err(error) = action(*item) {
+            eprintln!("{}", style::error(format_args!("{}: {}", error, item)));
+            first_error.get_or_insert(error);
+        }
+    }
+
+    match first_error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+fn parse_timestamp(s: &str) -> Result<Time, String> {
+    let timestamp = s.parse::<u32>().map_err(|error| error.to_string())?;
+    Time::from_consensus(timestamp)
+        .map_err(|_| "timestamps below 500000000 are block heights".to_string())
+}
+
+/// Parse an amount in satoshi, with optional unit suffix "btc" or "sat"
+fn parse_amount(s: &str) -> Result<u64, String> {
+    let s = s.to_lowercase();
+    let amount = if let Some(btc) = s.strip_suffix("btc") {
+        bitcoin::Amount::from_str_in(btc.trim(), bitcoin::Denomination::Bitcoin)
+            .map_err(|error| error.to_string())?
+    } else {
+        let sat = s.strip_suffix("sat").unwrap_or(&s);
+        bitcoin::Amount::from_str_in(sat.trim(), bitcoin::Denomination::Satoshi)
+            .map_err(|error| error.to_string())?
+    };
+
+    if amount > bitcoin::Amount::MAX_MONEY {
+        return Err("amount exceeds 21 million bitcoin".to_string());
+    }
+    Ok(amount.to_sat())
+}
+
+/// Parse a 32-bit sequence, in hex if prefixed by "0x"
+fn parse_sequence(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|error| error.to_string())
+}
+
+fn parse_secret_key(s: &str) -> Result<bitcoin::secp256k1::SecretKey, String> {
+    s.parse().map_err(|_| {
+        "secret key must be 64 hex digits, not zero and below the curve order".to_string()
+    })
+}
+
+fn parse_feerate(s: &str) -> Result<f64, String> {
+    let feerate = s.parse::<f64>().map_err(|error| error.to_string())?;
+    if !feerate.is_finite() || feerate < 0.0 {
+        return Err("feerate must be a non-negative number".to_string());
+    }
+    Ok(feerate)
+}
+
+fn parse_descriptor(s: &str) -> Result<Descriptor<bitcoin::XOnlyPublicKey>, String> {
+    descriptor::parse(s).map_err(|error| error.to_string())
+}
+
+fn parse_leaf(s: &str) -> Result<(u8, Miniscript<bitcoin::XOnlyPublicKey, Tap>), String> {
+    let (depth, miniscript) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected DEPTH:MINISCRIPT: {}", s))?;
+    let depth = depth
+        .parse()
+        .map_err(|_| format!("invalid leaf depth: {}", depth))?;
+    let miniscript = miniscript.parse().map_err(|error| format!("{}", error))?;
+    Ok((depth, miniscript))
+}
+
+fn parse_sighash(s: &str) -> Result<bitcoin::SchnorrSighashType, String> {
+    use bitcoin::SchnorrSighashType::*;
+
+    match s.to_lowercase().as_str() {
+        "default" => Ok(Default),
+        "all" => Ok(All),
+        "none" => Ok(None),
+        "single" => Ok(Single),
+        "all-anyonecanpay" => Ok(AllPlusAnyoneCanPay),
+        "none-anyonecanpay" => Ok(NonePlusAnyoneCanPay),
+        "single-anyonecanpay" => Ok(SinglePlusAnyoneCanPay),
+        _ => s
+            .parse()
+            .map_err(|_| format!("unknown sighash type: {}", s)),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    style::init(cli.color);
+
+    let result = Config::load(cli.config.as_deref()).and_then(|config| run(cli, config));
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", style::error(format_args!("Error: {}", error)));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn save_state(state: &State, state_file: &Path, backup: bool, verbose: bool) -> Result<(), Error> {
+    if verbose && state_file.exists() {
+        diff::print_changes(&State::load(state_file)?, state);
+    }
+    if backup {
+        backup::create_backup(state_file, backup::MAX_BACKUPS)?;
+    }
+    backup::save_undo(state_file)?;
+
+    state.save(state_file, false)
+}
+
+/// Cookie file and address of Bitcoin Core
+///
+/// Command line flags and environment variables take precedence over the config file
+fn rpc_settings(cli: &Cli, config: &Config) -> (Option<PathBuf>, Option<String>) {
+    (
+        cli.rpc_cookie.clone().or_else(|| config.rpc_cookie.clone()),
+        cli.rpc_connect
+            .clone()
+            .or_else(|| config.rpc_connect.clone()),
+    )
+}
+
+fn run(cli: Cli, config: Config) -> Result<(), Error> {
+    let (rpc_cookie, rpc_connect) = rpc_settings(&cli, &config);
+    rpc::init(rpc_cookie, rpc_connect);
+    let state_file = cli.state_file.as_path();
+    let backup = !cli.no_backup;
+    let yes = cli.yes;
+    let verbose = cli.verbose;
+
+    // Other commands neither call the node nor rewrite the state file behind the user's back
+    let recovers = matches!(
+        cli.command,
+        Command::Status { .. }
+            | Command::Final { .. }
+            | Command::Spend {
+                broadcast: true,
+                ..
+            }
+    );
+    if recovers && state_file.exists() {
+        recover_pending_broadcast(state_file, backup, yes, verbose)?;
+    }
+
+    match cli.command {
+        Command::Init => {
+            let mut state = State::new();
+            if let Some(network) = config.network {
+                state.network = network;
+            }
+            if let Some(fee) = config.fee {
+                state.fee = fee;
+            }
+            state.feerate = config.feerate;
+            println!("Generating {}", state_file.display());
+            state.save(state_file, true)?;
+            backup::clear_undo(state_file)?;
+        }
+        Command::Print { json } => {
+            let state = State::load(state_file)?;
+
+            if json {
+                let view = json::StateView::new(&state);
+                println!("{}", serde_json::to_string_pretty(&view)?);
+            } else {
+                println!("{}", state);
+            }
+        }
+        Command::Status { oneline } => {
+            let state = State::load(state_file)?;
+            let summary = state.summary();
+
+            if oneline {
+                let line = summary
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .join(" ");
+                println!("{}", line);
+            } else {
+                for (name, value) in summary {
+                    println!("{}: {}", name, value);
+                }
+            }
+        }
+        Command::Key {
+            key_command: KeyCommand::Sign { key, message },
+        } => {
+            let state = State::load(state_file)?;
+            let signature = key::sign_message(&state, key, &message)?;
+            println!("{}", signature);
+        }
+        Command::Key {
+            key_command:
+                KeyCommand::Verify {
+                    key,
+                    message,
+                    signature,
+                },
+        } => {
+            key::verify_message(key, &message, &signature)?;
+            println!("Valid signature");
+        }
+        Command::Key {
+            key_command: KeyCommand::Edit(key_command),
+        } => {
+            let mut state = State::load(state_file)?;
+
+            match key_command {
+                KeyEditCommand::Gen { number } => {
+                    key::generate_keys(&mut state, number)?;
+                }
+                KeyEditCommand::GenVanity { prefix, max_tries } => {
+                    key::generate_vanity_key(&mut state, &prefix, max_tries)?;
+                }
+                KeyEditCommand::Seed { mnemonic, number } => {
+                    key::derive_keys(&mut state, &mnemonic, number)?;
+                }
+                KeyEditCommand::Import { private_key } => {
+                    let key = key::import_key(&mut state, private_key.inner)?;
+                    println!("{}", style::added(format_args!("New key: {}", key)));
+                }
+                KeyEditCommand::FromSecret { secret_key } => {
+                    let key = key::import_key(&mut state, secret_key)?;
+                    println!("{}", style::added(format_args!("New key: {}", key)));
+                }
+                KeyEditCommand::En { keys } => {
+                    let keys = resolve_all(&keys, |key| key::resolve_key(&state, key))?;
+                    for_each_item(&keys, |key| {
+                        key::enable_key(&mut state, key)?;
+                        println!("Enabling key: {}", key);
+                        Ok(())
+                    })?;
+                }
+                KeyEditCommand::EnAll => {
+                    let number = key::enable_all_keys(&mut state);
+                    println!("Enabled keys: {}", number);
+                }
+                KeyEditCommand::Dis { keys } => {
+                    let keys = resolve_all(&keys, |key| key::resolve_key(&state, key))?;
+                    for_each_item(&keys, |key| {
+                        key::disable_key(&mut state, key)?;
+                        println!("Disabling key: {}", key);
+                        Ok(())
+                    })?;
+                }
+                KeyEditCommand::DisAll => {
+                    let number = key::disable_all_keys(&mut state);
+                    println!("Disabled keys: {}", number);
                 }
-                AddrCommand::Utxo {
+                KeyEditCommand::Del { keys } => {
+                    let keys = resolve_all(&keys, |key| key::resolve_key(&state, key))?;
+                    confirm_delete(format_args!("{} key pair(s)", keys.len()), yes)?;
+                    for_each_item(&keys, |key| {
+                        match key::delete_key(&mut state, &key)? {
+                            Some(old) => println!(
+                                "{}",
+                                style::removed(format_args!(
+                                    "Deleting key pair: {}",
+                                    old.display_secret()
+                                ))
+                            ),
+                            None => println!(
+                                "{}",
+                                style::removed(format_args!("Deleting watch-only key: {}", key))
+                            ),
+                        }
+                        Ok(())
+                    })?;
+                }
+                KeyEditCommand::Watch { key } => {
+                    key::watch_key(&mut state, key)?;
+                    println!(
+                        "{}",
+                        style::added(format_args!("New watch-only key: {}", key))
+                    );
+                }
+                KeyEditCommand::Label { key, label } => {
+                    key::label_key(&mut state, key, label)?;
+                }
+            }
+
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Img {
+            img_command: ImgCommand::Digests { image },
+        } => {
+            let state = State::load(state_file)?;
+            image::print_digests(&state, image)?;
+        }
+        Command::Img {
+            img_command: ImgCommand::Edit(img_command),
+        } => {
+            let mut state = State::load(state_file)?;
+
+            match img_command {
+                ImgEditCommand::Gen { number } => {
+                    image::generate_images(&mut state, number)?;
+                }
+                ImgEditCommand::Derive { key, index } => {
+                    image::derive_image(&mut state, key, index)?;
+                }
+                ImgEditCommand::Import { preimage } => {
+                    let image = image::import_image(&mut state, &preimage)?;
+                    println!("{}", style::added(format_args!("New image: {}", image)));
+                }
+                ImgEditCommand::Watch { image } => {
+                    image::watch_image(&mut state, image)?;
+                    println!(
+                        "{}",
+                        style::added(format_args!("Watching image: {}", image))
+                    );
+                }
+                ImgEditCommand::En { images } => {
+                    let images = resolve_all(&images, |image| image::resolve_image(&state, image))?;
+                    for_each_item(&images, |image| {
+                        image::enable_image(&mut state, image)?;
+                        println!("Enabling image: {}", image);
+                        Ok(())
+                    })?;
+                }
+                ImgEditCommand::EnAll => {
+                    let number = image::enable_all_images(&mut state);
+                    println!("Enabled images: {}", number);
+                }
+                ImgEditCommand::Dis { images } => {
+                    let images = resolve_all(&images, |image| image::resolve_image(&state, image))?;
+                    for_each_item(&images, |image| {
+                        image::disable_image(&mut state, image)?;
+                        println!("Disabling image: {}", image);
+                        Ok(())
+                    })?;
+                }
+                ImgEditCommand::DisAll => {
+                    let number = image::disable_all_images(&mut state);
+                    println!("Disabled images: {}", number);
+                }
+                ImgEditCommand::Del { images } => {
+                    let images = resolve_all(&images, |image| image::resolve_image(&state, image))?;
+                    confirm_delete(format_args!("{} (pre)image pair(s)", images.len()), yes)?;
+                    for_each_item(&images, |image| {
+                        match image::delete_image(&mut state, &image)? {
+                            Some(old) => {
+                                let preimage =
+                                    old.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+                                println!(
+                                    "{}",
+                                    style::removed(format_args!(
+                                        "Deleting (pre)image pair: {}",
+                                        preimage
+                                    ))
+                                );
+                            }
+                            None => {
+                                println!(
+                                    "{}",
+                                    style::removed(format_args!(
+                                        "Deleting watch-only image: {}",
+                                        image
+                                    ))
+                                );
+                            }
+                        }
+                        Ok(())
+                    })?;
+                }
+                ImgEditCommand::Label { image, label } => {
+                    image::label_image(&mut state, image, label)?;
+                }
+            }
+
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Descriptor { descriptor_command } => match descriptor_command {
+            DescriptorCommand::Diff { first, second } => {
+                descriptor::diff_descriptors(&first, &second)?;
+            }
+            DescriptorCommand::Info { descriptor } => {
+                descriptor::print_info(&descriptor)?;
+            }
+            DescriptorCommand::Tree {
+                internal_key,
+                leaves,
+            } => {
+                let descriptor = descriptor::build_tree(internal_key, leaves)?;
+                println!("{}", descriptor);
+            }
+        },
+        Command::Template {
+            template_command: TemplateCommand::Use { name, arguments },
+        } => {
+            let state = State::load(state_file)?;
+            let descriptor = template::use_template(&state, &name, &arguments)?;
+            println!("{}", descriptor);
+        }
+        Command::Template {
+            template_command: TemplateCommand::List,
+        } => {
+            let state = State::load(state_file)?;
+            template::list_templates(&state);
+        }
+        Command::Template {
+            template_command: TemplateCommand::Edit(template_command),
+        } => {
+            let mut state = State::load(state_file)?;
+
+            match template_command {
+                TemplateEditCommand::Save { name, template } => {
+                    let message = format!("New template {}: {}", name, template);
+                    let old = template::save_template(&mut state, name, template)?;
+                    println!("{}", style::added(message));
+                    if let Some(template) = old {
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing template: {}", template))
+                        );
+                    }
+                }
+                TemplateEditCommand::Del { name } => {
+                    let old = template::delete_template(&mut state, &name)?;
+                    println!(
+                        "{}",
+                        style::removed(format_args!("Deleting template {}: {}", name, old))
+                    );
+                }
+            }
+
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Addr {
+            addr_command: AddrCommand::Show { descriptor },
+        } => {
+            let state = State::load(state_file)?;
+            let address = address::get_address(&state, &descriptor)?;
+            println!("Address: {}", address);
+        }
+        Command::Addr {
+            addr_command:
+                AddrCommand::Verify {
+                    address,
+                    descriptor,
+                },
+        } => {
+            let state = State::load(state_file)?;
+            address::verify_address(&state, &address, &descriptor)?;
+        }
+        Command::Addr {
+            addr_command: AddrCommand::Weights { descriptor },
+        } => {
+            descriptor::print_leaf_weights(&descriptor)?;
+        }
+        Command::Addr {
+            addr_command: AddrCommand::Qr { index, descriptor },
+        } => {
+            let state = State::load(state_file)?;
+            let address = match descriptor {
+                Some(descriptor) => address::get_address(&state, &descriptor)?,
+                None => address::get_inbound_address(&state, index)?,
+            };
+            address::print_qr(&address)?;
+            println!("Address: {}", address);
+        }
+        Command::Addr {
+            addr_command: AddrCommand::FundCmd { value, index },
+        } => {
+            let state = State::load(state_file)?;
+            let address = address::get_inbound_address(&state, index)?;
+            println!("{}", address::fund_command(&address, value));
+        }
+        Command::Addr {
+            addr_command: AddrCommand::Edit(addr_command),
+        } => {
+            let mut state = State::load(state_file)?;
+
+            match addr_command {
+                AddrEditCommand::Set { descriptor, file } => {
+                    let descriptor = match file {
+                        Some(path) => util::read_descriptor(path)?,
+                        None => descriptor.expect("required without file"),
+                    };
+                    let (index, address) = address::set_address(&mut state, descriptor)?;
+                    println!(
+                        "{}",
+                        style::added(format_args!(
+                            "New inbound address #{}: {}",
+                            index, state.inbound_addresses[&index]
+                        ))
+                    );
+                    println!("Fund this address: {}", address);
+                }
+                AddrEditCommand::Keypath { key } => {
+                    let pubkey = key::resolve_key(&state, &key)?;
+                    let (index, address) = address::set_keypath_address(&mut state, pubkey)?;
+                    println!(
+                        "{}",
+                        style::added(format_args!(
+                            "New inbound address #{}: {}",
+                            index, state.inbound_addresses[&index]
+                        ))
+                    );
+                    println!("Fund this address: {}", address);
+                }
+                AddrEditCommand::Del { index } => {
+                    let old = address::delete_address(&mut state, index)?;
+                    println!(
+                        "{}",
+                        style::removed(format_args!("Deleting inbound address: {}", old))
+                    );
+                }
+                AddrEditCommand::Utxo {
+                    index,
                     txid,
                     output_index,
                     value,
+                    from_core,
+                    verify,
                 } => {
-                    address::into_utxo(&mut state, txid, output_index, value)?;
+                    let (txid, output_index, value) = match from_core {
+                        Some(json) => address::find_funding_output(&state, index, &json)?,
+                        None => (
+                            txid.expect("required without from_core"),
+                            output_index.expect("required without from_core"),
+                            value.expect("required without from_core"),
+                        ),
+                    };
+                    address::into_utxo(&mut state, index, txid, output_index, value, verify)?;
+                }
+                AddrEditCommand::Scan => {
+                    address::scan_addresses(&mut state)?;
                 }
             }
 
-            state.save(STATE_FILE_NAME, false)?;
+            save_state(&state, state_file, backup, verbose)?;
         }
-        Command::Utxo { utxo_command } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+        Command::Utxo {
+            utxo_command: UtxoCommand::List { json },
+        } => {
+            let state = State::load(state_file)?;
+
+            if json {
+                let views = json::UtxoView::list(&state);
+                println!("{}", serde_json::to_string_pretty(&views)?);
+            } else {
+                utxo::list_utxos(&state);
+            }
+        }
+        Command::Utxo {
+            utxo_command: UtxoCommand::Edit(utxo_command),
+        } => {
+            let mut state = State::load(state_file)?;
 
             match utxo_command {
-                UtxoCommand::List => {
-                    utxo::list_utxos(&state);
-                }
-                UtxoCommand::Del { utxo_index } => {
+                UtxoEditCommand::Del { utxo_index } => {
+                    confirm_delete(format_args!("UTXO {}", utxo_index), yes)?;
                     let old = utxo::delete_utxo(&mut state, utxo_index)?;
-                    println!("Deleting UTXO: {}", old);
+                    println!("{}", style::removed(format_args!("Deleting UTXO: {}", old)));
+                }
+                UtxoEditCommand::Label { utxo_index, label } => {
+                    utxo::label_utxo(&mut state, utxo_index, label)?;
+                }
+                UtxoEditCommand::FromTx {
+                    tx_hex,
+                    descriptors,
+                } => {
+                    utxo::import_transaction(&mut state, &tx_hex, descriptors)?;
+                }
+                UtxoEditCommand::Import { path, strict } => {
+                    utxo::import_utxos(&mut state, path, strict)?;
                 }
             }
 
-            state.save(STATE_FILE_NAME, false)?;
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::In {
+            index,
+            in_command: InCommand::Addr,
+        } => {
+            let state = State::load(state_file)?;
+            let address = input::get_input_address(&state, index)?;
+            println!("Address: {}", address);
         }
-        Command::In { index, in_command } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+        Command::In {
+            index,
+            in_command: InCommand::Edit(in_command),
+        } => {
+            let mut state = State::load(state_file)?;
 
             match in_command {
-                InCommand::New { utxo_index } => {
+                InEditCommand::New { utxo_index } => {
                     let old = input::add_from_utxo(&mut state, index, utxo_index)?;
 
                     if let Some(input) = old {
-                        println!("Replacing input: {}", input);
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing input: {}", input))
+                        );
                     }
                 }
-                InCommand::Del => {
+                InEditCommand::Raw {
+                    txid,
+                    vout,
+                    value,
+                    descriptor,
+                } => {
+                    let outpoint = bitcoin::OutPoint { txid, vout };
+                    let old =
+                        input::add_from_outpoint(&mut state, index, outpoint, value, descriptor)?;
+
+                    if let Some(input) = old {
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing input: {}", input))
+                        );
+                    }
+                }
+                InEditCommand::Select {
+                    target,
+                    largest_first,
+                    smallest_first,
+                } => {
+                    let order = match (largest_first, smallest_first) {
+                        (false, true) => input::SelectOrder::SmallestFirst,
+                        _ => input::SelectOrder::LargestFirst,
+                    };
+                    let change = input::select_utxos(&mut state, index, target, order)?;
+                    println!("Change: {} sat", change);
+                }
+                InEditCommand::Dup { utxo_indices } => {
+                    let new_indices = input::duplicate_input(&mut state, index, &utxo_indices)?;
+                    println!("New inputs: {}", new_indices.iter().join(", "));
+                }
+                InEditCommand::Del => {
+                    confirm_delete(format_args!("input {}", index), yes)?;
                     let old = input::delete_input(&mut state, index)?;
-                    println!("Deleting input: {}", old);
+                    println!(
+                        "{}",
+                        style::removed(format_args!("Deleting input: {}", old))
+                    );
                 }
-                InCommand::Seq { seq_command } => match seq_command {
+                InEditCommand::Seq { seq_command } => match seq_command {
                     SeqCommand::Enable { relative_height } => {
                         let locktime_before = state.locktime_enabled();
                         input::update_sequence_height(&mut state, index, relative_height)?;
@@ -354,6 +1783,16 @@ fn main() -> Result<(), Error> {
                             println!("Locktime: enabled");
                         }
                     }
+                    SeqCommand::EnableTime { relative_seconds } => {
+                        let locktime_before = state.locktime_enabled();
+                        let seconds =
+                            input::update_sequence_time(&mut state, index, relative_seconds)?;
+                        println!("Relative timelock: +{} seconds", seconds);
+
+                        if !locktime_before {
+                            println!("Locktime: enabled");
+                        }
+                    }
                     SeqCommand::Disable => {
                         input::set_sequence_max(&mut state, index)?;
                         println!("Relative timelock: disabled");
@@ -362,60 +1801,712 @@ fn main() -> Result<(), Error> {
                             println!("Locktime: disabled");
                         }
                     }
+                    SeqCommand::Raw { sequence } => {
+                        let locktime_before = state.locktime_enabled();
+                        input::update_sequence_raw(&mut state, index, bitcoin::Sequence(sequence))?;
+                        println!("Sequence: {:#010x}", sequence);
+
+                        match (locktime_before, state.locktime_enabled()) {
+                            (false, true) => println!("Locktime: enabled"),
+                            (true, false) => println!("Locktime: disabled"),
+                            _ => {}
+                        }
+                    }
                 },
+                InEditCommand::Move { to } => {
+                    input::move_input(&mut state, index, to)?;
+                    println!("Moving input #{} to #{}", index, to);
+                }
+                InEditCommand::Sighash { sighash } => {
+                    input::update_sighash(&mut state, index, sighash)?;
+                    println!("Sighash: {}", sighash);
+                }
+                InEditCommand::Rbf => {
+                    let locktime_before = state.locktime_enabled();
+                    input::enable_rbf(&mut state, index)?;
+                    println!("Replace-by-fee: enabled");
+
+                    if !locktime_before {
+                        println!("Locktime: enabled");
+                    }
+                }
+                InEditCommand::RbfOff => {
+                    input::set_sequence_max(&mut state, index)?;
+                    println!("Replace-by-fee: disabled");
+
+                    if !state.locktime_enabled() {
+                        println!("Locktime: disabled");
+                    }
+                }
             }
 
-            state.save(STATE_FILE_NAME, false)?;
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Out {
+            index,
+            out_command: OutCommand::Show,
+        } => {
+            let state = State::load(state_file)?;
+            output::show_output(&state, index)?;
         }
-        Command::Out { index, out_command } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+        Command::Out {
+            index,
+            out_command: OutCommand::Edit(out_command),
+        } => {
+            let mut state = State::load(state_file)?;
 
             match out_command {
-                OutCommand::New { descriptor, value } => {
-                    let old = output::add_output(&mut state, index, descriptor, value)?;
+                OutEditCommand::New {
+                    descriptor,
+                    value,
+                    file,
+                    weight,
+                } => {
+                    // With a file, the only positional argument is the value
+                    let (descriptor, value) = match file {
+                        Some(path) => {
+                            let value = descriptor
+                                .as_deref()
+                                .map(parse_amount)
+                                .transpose()
+                                .map_err(Error::InvalidAmount)?;
+                            (util::read_descriptor(path)?, value)
+                        }
+                        None => {
+                            let descriptor = descriptor.expect("required without file");
+                            (descriptor::parse(&descriptor)?, value)
+                        }
+                    };
+                    let value = value.unwrap_or(0);
+                    let old = output::add_output(&mut state, index, descriptor, value, weight)?;
+
+                    if let Some(output) = old {
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing output: {}", output))
+                        );
+                    }
+                }
+                OutEditCommand::Pay {
+                    address,
+                    value,
+                    weight,
+                } => {
+                    let old =
+                        output::add_address_output(&mut state, index, address, value, weight)?;
 
                     if let Some(output) = old {
-                        println!("Replacing output: {}", output);
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing output: {}", output))
+                        );
                     }
                 }
-                OutCommand::Del => {
+                OutEditCommand::Script {
+                    script_pubkey,
+                    value,
+                    weight,
+                } => {
+                    let old = output::add_script_output(
+                        &mut state,
+                        index,
+                        &script_pubkey,
+                        value,
+                        weight,
+                    )?;
+
+                    if let Some(output) = old {
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing output: {}", output))
+                        );
+                    }
+                }
+                OutEditCommand::Data { data } => {
+                    let old = output::add_data_output(&mut state, index, &data)?;
+
+                    if let Some(output) = old {
+                        println!(
+                            "{}",
+                            style::removed(format_args!("Replacing output: {}", output))
+                        );
+                    }
+                }
+                OutEditCommand::Dup { count } => {
+                    let new_indices = output::duplicate_output(&mut state, index, count)?;
+                    println!("New outputs: {}", new_indices.iter().join(", "));
+                }
+                OutEditCommand::Del => {
+                    confirm_delete(format_args!("output {}", index), yes)?;
                     let old = output::delete_output(&mut state, index)?;
-                    println!("Deleting output: {}", old);
+                    println!(
+                        "{}",
+                        style::removed(format_args!("Deleting output: {}", old))
+                    );
+                }
+                OutEditCommand::Move { to } => {
+                    output::move_output(&mut state, index, to)?;
+                    println!("Moving output #{} to #{}", index, to);
                 }
             }
 
-            state.save(STATE_FILE_NAME, false)?;
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Network { network } => {
+            let mut state = State::load(state_file)?;
+
+            match network {
+                Some(network) => {
+                    state.network = network;
+                    println!("Network: {}", network);
+                    save_state(&state, state_file, backup, verbose)?;
+                }
+                None => {
+                    println!("Network: {}", state.network);
+                }
+            }
         }
-        Command::Locktime { height } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
-            transaction::update_locktime(&mut state, height)?;
-            println!("Locktime: ={} blocks", height);
+        Command::Tx { tx_command } => {
+            let mut state = State::load(state_file)?;
 
-            if !state.locktime_enabled() {
-                println!("Locktime: disabled (enable via input sequence)");
+            match tx_command {
+                TxCommand::Clear => {
+                    confirm_delete(format_args!("the current transaction"), yes)?;
+                    transaction::clear_transaction(&mut state);
+                }
             }
 
-            state.save(STATE_FILE_NAME, false)?;
+            save_state(&state, state_file, backup, verbose)?;
         }
-        Command::Fee { value } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
-            transaction::update_fee(&mut state, value)?;
-            println!("Fee: {} sat", value);
-            state.save(STATE_FILE_NAME, false)?;
+        Command::Version { version } => {
+            let mut state = State::load(state_file)?;
+
+            match version {
+                Some(version) => {
+                    transaction::update_version(&mut state, version)?;
+                    println!("Transaction version: {}", version);
+
+                    if version < 2 && state.has_relative_locktime() {
+                        println!(
+                            "{}",
+                            style::error(
+                                "Relative locktimes (BIP68) require transaction version 2 or higher"
+                            )
+                        );
+                    }
+
+                    save_state(&state, state_file, backup, verbose)?;
+                }
+                None => {
+                    println!("Transaction version: {}", state.tx_version);
+                }
+            }
+        }
+        Command::Locktime {
+            height,
+            locktime_command,
+        } => {
+            let mut state = State::load(state_file)?;
+            let locktime = match (height, locktime_command) {
+                (_, Some(LocktimeCommand::Time { timestamp })) => LockTime::Seconds(timestamp),
+                (_, Some(LocktimeCommand::Off)) => LockTime::ZERO,
+                (Some(height), None) => LockTime::Blocks(height),
+                (None, None) => {
+                    println!("Locktime: {}", state.locktime_description());
+                    return Ok(());
+                }
+            };
+            transaction::update_locktime(&mut state, locktime)?;
+            println!("Locktime: {}", state.locktime_description());
+
+            if locktime == LockTime::ZERO {
+                println!(
+                    "A nonzero locktime also requires an input whose sequence is not 0xffffffff"
+                );
+            }
+
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Fee {
+            value,
+            from_output,
+            from_inputs,
+            fee_command,
+        } => {
+            let mut state = State::load(state_file)?;
+
+            match (value, fee_command) {
+                (_, Some(FeeCommand::ChainEstimate { depth, feerate })) => {
+                    transaction::estimate_chain_fees(&state, depth, feerate)?;
+                }
+                (value, None) if value.is_some() || from_output.is_some() || from_inputs => {
+                    if let Some(value) = value {
+                        transaction::update_fee(&mut state, value)?;
+                        println!("Fee: {} sat", value);
+                    }
+                    if from_output.is_some() || from_inputs {
+                        transaction::update_fee_output(&mut state, from_output)?;
+                        match from_output {
+                            Some(index) => println!("Fee is paid by output #{}", index),
+                            None => println!("Fee is paid by the inputs"),
+                        }
+                    }
+                    save_state(&state, state_file, backup, verbose)?;
+                }
+                (_, None) => match util::get_fee_output(&state) {
+                    Some(index) => println!("Fee: {} sat (paid by output #{})", state.fee, index),
+                    None => println!("Fee: {} sat", state.fee),
+                },
+            }
+        }
+        Command::Check => {
+            let state = State::load(state_file)?;
+            match check::check_transaction(&state) {
+                0 => {}
+                failed => return Err(Error::ChecksFailed(failed)),
+            }
+        }
+        Command::Estimate => {
+            let state = State::load(state_file)?;
+            transaction::print_estimate(&state)?;
+        }
+        Command::Sweep {
+            utxo_index,
+            descriptor,
+            feerate,
+            force,
+        } => {
+            let mut state = State::load(state_file)?;
+            let is_empty = state.inputs.is_empty() && state.outputs.is_empty();
+            if !force && !is_empty {
+                return Err(Error::ForceRequired);
+            }
+
+            let value = transaction::sweep_utxo(&mut state, utxo_index, descriptor, feerate)?;
+            println!("Output #0 receives: {} sat", value);
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::RbfReplace {
+            txid,
+            feerate,
+            change,
+            force,
+        } => {
+            let mut state = State::load(state_file)?;
+            // Inputs that spend outputs of the replaced transaction are discarded anyway
+            let is_stale = state.outputs.is_empty()
+                && state
+                    .inputs
+                    .values()
+                    .all(|input| input.utxo.outpoint.txid == txid);
+            if !force && !is_stale {
+                return Err(Error::ForceRequired);
+            }
+
+            let fee = transaction::replace_transaction(&mut state, txid, feerate, change)?;
+            println!("Fee: {} sat", fee);
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Feerate { feerate } => {
+            let mut state = State::load(state_file)?;
+
+            match feerate {
+                Some(feerate) => {
+                    transaction::update_feerate(&mut state, feerate)?;
+                    println!("Feerate target: {} sat / vB", feerate);
+                    save_state(&state, state_file, backup, verbose)?;
+                }
+                None => match state.feerate {
+                    Some(feerate) => println!("Feerate target: {} sat / vB", feerate),
+                    None => println!("Feerate target: none (fixed fee of {} sat)", state.fee),
+                },
+            }
+        }
+        Command::Spend {
+            from_psbt: Some(mut psbt),
+            ..
+        } => {
+            let state = State::load(state_file)?;
+            let signatures = spend::sign_psbt(&state, &mut psbt)?;
+            println!("Added signatures: {}\n", signatures);
+            println!("Signed PSBT: {}", psbt);
+        }
+        Command::Spend {
+            output_order,
+            broadcast,
+            finalize,
+            psbt,
+            from_psbt: None,
+            dry_run,
+            skip_check,
+            burn_excess,
+            estimate_fee,
+            fallback_feerate,
+            dump_witness,
+            partial,
+            json,
+        } => {
+            let mut state = State::load(state_file)?;
+
+            if let Some(order) = output_order {
+                output::reorder_outputs(&mut state, &order)?;
+            }
+
+            let feerate = match estimate_fee {
+                Some(conf_target) => match rpc::estimate_feerate(conf_target)? {
+                    Some(feerate) => {
+                        if !json {
+                            println!(
+                                "Estimated feerate: {:.2} sat / vB (confirmation within {} blocks)",
+                                feerate, conf_target
+                            );
+                        }
+                        Some(feerate)
+                    }
+                    None => {
+                        let fallback_feerate =
+                            fallback_feerate.or(config.fallback_feerate).unwrap_or(1.0);
+                        eprintln!(
+                            "{}",
+                            style::error(format_args!(
+                                "Warning: No feerate estimate for {} blocks; using {} sat / vB",
+                                conf_target, fallback_feerate
+                            ))
+                        );
+                        Some(fallback_feerate)
+                    }
+                },
+                None => state.feerate,
+            };
+            if let Some(feerate) = feerate {
+                let vsize = transaction::apply_feerate(&mut state, feerate)?;
+                if !json {
+                    println!("Fee: {} sat (estimated {} vB)", state.fee, vsize);
+                }
+            }
+
+            if !burn_excess {
+                util::check_unassigned_funds(&state)?;
+            }
+
+            if psbt {
+                let psbt = spend::get_psbt(&state)?;
+                spend::record_spend(&mut state, psbt.unsigned_tx.clone());
+                println!("Unsigned PSBT: {}", psbt);
+            } else {
+                let (tx_hex, fee, vsize, unsigned) =
+                    spend::get_raw_transaction(&mut state, skip_check, partial)?;
+                if json {
+                    let view = json::SpendView::new(&state, tx_hex, fee, unsigned)?;
+                    println!("{}", serde_json::to_string_pretty(&view)?);
+                    if !dry_run {
+                        save_state(&state, state_file, backup, verbose)?;
+                    }
+                    return Ok(());
+                }
+                if dump_witness {
+                    transaction::print_witnesses(&tx_hex)?;
+                }
+                println!("Feerate: {:.2} sat / vB\n", fee as f64 / vsize as f64);
+                let bounds = (
+                    config.min_feerate.unwrap_or(util::MIN_PLAUSIBLE_FEERATE),
+                    config.max_feerate.unwrap_or(util::MAX_PLAUSIBLE_FEERATE),
+                );
+                if let Some(warning) = util::feerate_warning(fee, vsize, bounds) {
+                    println!("{}\n", style::error(warning));
+                }
+
+                if broadcast {
+                    // Signing recorded the spend
+                    let spend = state.last_spend.as_ref().expect("recorded spend");
+                    pending::mark(state_file, spend)?;
+                    let txid = match rpc::send_transaction(&tx_hex) {
+                        Ok(txid) => txid,
+                        Err(error) => {
+                            pending::clear(state_file)?;
+                            return Err(error);
+                        }
+                    };
+                    println!("Broadcast transaction: {}", txid);
+
+                    if finalize {
+                        transaction::finalize_transaction(&mut state, Some(txid))?;
+                    }
+                } else if !unsigned.is_empty() {
+                    println!(
+                        "{}",
+                        style::error(format_args!(
+                            "Unsigned inputs: {} (the feerate excludes their witnesses)",
+                            unsigned.iter().join(", ")
+                        ))
+                    );
+                    println!("Partially signed transaction: {}", tx_hex);
+                } else {
+                    println!("Send this transaction: {}", tx_hex);
+                }
+            }
+
+            if !dry_run {
+                save_state(&state, state_file, backup, verbose)?;
+                if broadcast {
+                    pending::clear(state_file)?;
+                }
+            }
         }
-        Command::Spend => {
-            let mut state = State::load(STATE_FILE_NAME)?;
-            let (tx_hex, feerate) = spend::get_raw_transaction(&mut state)?;
-            println!("Feerate: {:.2} sat / vB\n", feerate);
-            println!("Send this transaction: {}", tx_hex);
-            state.save(STATE_FILE_NAME, false)?;
+        Command::Decode { tx_hex } => {
+            transaction::print_transaction(&tx_hex)?;
         }
         Command::Final { txid } => {
-            let mut state = State::load(STATE_FILE_NAME)?;
+            let mut state = State::load(state_file)?;
             transaction::finalize_transaction(&mut state, txid)?;
-            state.save(STATE_FILE_NAME, false)?;
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::RebuildUtxos { force } => {
+            let mut state = State::load(state_file)?;
+            utxo::rebuild_utxos(&mut state, force)?;
+            save_state(&state, state_file, backup, verbose)?;
+        }
+        Command::Undo => {
+            let undo_path = backup::undo_path(state_file);
+            if !undo_path.exists() {
+                return Err(Error::NothingToUndo);
+            }
+            // Make sure the undo file holds a valid state before swapping
+            State::load(&undo_path)?;
+            backup::undo(state_file)?;
+            println!("Reverted the last change of {}", state_file.display());
+        }
+        Command::Backup { backup_command } => match backup_command {
+            BackupCommand::List => {
+                for (timestamp, backup_path) in backup::list_backups(state_file)? {
+                    let size = std::fs::metadata(&backup_path)?.len();
+                    println!(
+                        "{} ({}): {} bytes",
+                        timestamp,
+                        util::format_utc(timestamp),
+                        size
+                    );
+                }
+            }
+            BackupCommand::Prune { keep } => {
+                let number = backup::list_backups(state_file)?.len();
+                if keep < number {
+                    confirm_delete(format_args!("{} backup(s)", number - keep), yes)?;
+                }
+                for backup_path in backup::prune_backups(state_file, keep)? {
+                    println!(
+                        "{}",
+                        style::removed(format_args!("Deleting backup: {}", backup_path.display()))
+                    );
+                }
+            }
+            BackupCommand::Restore { timestamp } => {
+                let backup_path = backup::find_backup(state_file, timestamp)?;
+                // Make sure the backup holds a valid state before restoring
+                State::load(&backup_path)?;
+                backup::restore_backup(state_file, &backup_path)?;
+                println!("Restored {}", backup_path.display());
+            }
+        },
+        Command::ImportState { path } => {
+            let mut state = State::load(state_file)?;
+            merge::import_state(&mut state, path)?;
+            save_state(&state, state_file, backup, verbose)?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// State file with one enabled key and a transaction that spends to it
+    fn spendable_state_file(name: &str) -> PathBuf {
+        let path = test_util::test_dir(name).join("state.json");
+
+        let mut state = State::new();
+        let descriptor = test_util::new_descriptor(&mut state);
+
+        let outpoint = bitcoin::OutPoint::default();
+        input::add_from_outpoint(&mut state, 0, outpoint, 100_000, descriptor.clone()).unwrap();
+        output::add_output(&mut state, 0, descriptor, 0, 1).unwrap();
+        state.save(&path, true).unwrap();
+        path
+    }
+
+    fn run_args(path: &Path, args: &[&str]) -> Result<(), Error> {
+        let state_file = path.to_str().unwrap();
+        let cli = Cli::parse_from(
+            ["tappy", "--no-backup", "--state-file", state_file]
+                .iter()
+                .chain(args),
+        );
+        run(cli, Config::default())
+    }
+
+    #[test]
+    fn dry_run_leaves_state_file_untouched() {
+        let path = spendable_state_file("dry-run");
+        let bytes = std::fs::read(&path).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        // Any rewrite of the file would get a later modification time
+        std::thread::sleep(Duration::from_millis(50));
+
+        run_args(&path, &["spend", "--dry-run"]).unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        let modified_after = std::fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(modified_after, modified);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn spend_without_dry_run_saves_state() {
+        let path = spendable_state_file("spend");
+        let bytes = std::fs::read(&path).unwrap();
+
+        run_args(&path, &["spend"]).unwrap();
+
+        assert_ne!(std::fs::read(&path).unwrap(), bytes);
+        assert!(State::load(&path).unwrap().last_spend.is_some());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn pending_broadcast_of_finalized_spend_is_cleared() {
+        let path = spendable_state_file("pending");
+        run_args(&path, &["spend"]).unwrap();
+        let spend = State::load(&path).unwrap().last_spend.unwrap();
+        run_args(&path, &["final"]).unwrap();
+
+        // The node is not asked, because the history already has the transaction
+        pending::mark(&path, &spend).unwrap();
+        run_args(&path, &["status"]).unwrap();
+
+        assert!(pending::load(&path).unwrap().is_none());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn read_only_command_ignores_pending_broadcast() {
+        let path = spendable_state_file("pending-print");
+        run_args(&path, &["spend"]).unwrap();
+        let spend = State::load(&path).unwrap().last_spend.unwrap();
+        run_args(&path, &["final"]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        pending::mark(&path, &spend).unwrap();
+        run_args(&path, &["print", "--json"]).unwrap();
+
+        assert!(pending::load(&path).unwrap().is_some());
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rpc_connect_flag_overrides_config() {
+        let config = Config {
+            rpc_connect: Some("config:18443".to_string()),
+            ..Config::default()
+        };
+
+        let cli = Cli::parse_from(["tappy", "--rpc-connect", "flag:18443", "status"]);
+        assert_eq!(rpc_settings(&cli, &config).1.as_deref(), Some("flag:18443"));
+        let cli = Cli::parse_from(["tappy", "status"]);
+        if std::env::var_os("TAPPY_RPC_CONNECT").is_none() {
+            assert_eq!(
+                rpc_settings(&cli, &config).1.as_deref(),
+                Some("config:18443")
+            );
+        }
+    }
+
+    /// Fund a key, spend the funds to a new key via broadcast and finalize the spend
+    ///
+    /// Needs `bitcoin-cli` connected to a regtest node with a funded wallet
+    /// (TAPPY_RPC_CONNECT and TAPPY_RPC_COOKIE are respected)
+    #[cfg(feature = "regtest-tests")]
+    #[test]
+    fn regtest_lifecycle() {
+        rpc::init(
+            std::env::var_os("TAPPY_RPC_COOKIE").map(PathBuf::from),
+            std::env::var("TAPPY_RPC_CONNECT").ok(),
+        );
+        let path = test_util::test_dir("regtest").join("state.json");
+        let mut state = State::new();
+        let funded = test_util::new_descriptor(&mut state);
+        let receiver = test_util::new_descriptor(&mut state);
+
+        let address = funded.address(bitcoin::Network::Regtest).unwrap();
+        let funding_txid: bitcoin::Txid =
+            rpc::call("sendtoaddress", &[&address.to_string(), "0.001"])
+                .unwrap()
+                .parse()
+                .unwrap();
+        let funding_tx = rpc::get_transaction(funding_txid).unwrap();
+        let vout = funding_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == funded.script_pubkey())
+            .unwrap();
+        let outpoint = bitcoin::OutPoint::new(funding_txid, vout as u32);
+        input::add_from_outpoint(&mut state, 0, outpoint, 100_000, funded).unwrap();
+        output::add_output(&mut state, 0, receiver.clone(), 0, 1).unwrap();
+        state.fee = 1_000;
+        state.save(&path, true).unwrap();
+
+        run_args(&path, &["spend", "--broadcast", "--finalize"]).unwrap();
+
+        let state = State::load(&path).unwrap();
+        let spend_txid = state.history.last().unwrap().txid;
+        assert!(rpc::get_transaction(spend_txid).is_ok());
+        assert!(state.utxos.iter().any(|utxo| {
+            utxo.outpoint == bitcoin::OutPoint::new(spend_txid, 0) && utxo.descriptor == receiver
+        }));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn tx_clear_with_yes_discards_transaction() {
+        let path = spendable_state_file("tx-clear");
+
+        run_args(&path, &["tx", "clear", "--yes"]).unwrap();
+
+        let state = State::load(&path).unwrap();
+        assert!(state.inputs.is_empty() && state.outputs.is_empty());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    /// Back up the state file, then change its fee
+    fn changed_after_backup(name: &str) -> (PathBuf, String) {
+        let path = spendable_state_file(name);
+        backup::create_backup(&path, backup::MAX_BACKUPS).unwrap();
+        let (timestamp, _) = backup::list_backups(&path).unwrap().remove(0);
+        run_args(&path, &["fee", "500"]).unwrap();
+        (path, timestamp.to_string())
+    }
+
+    #[test]
+    fn restore_after_change_succeeds() {
+        let (path, timestamp) = changed_after_backup("restore");
+
+        run_args(&path, &["backup", "restore", &timestamp]).unwrap();
+
+        assert_eq!(State::load(&path).unwrap().fee, State::new().fee);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn undo_reverts_restore() {
+        let (path, timestamp) = changed_after_backup("restore-undo");
+        run_args(&path, &["backup", "restore", &timestamp]).unwrap();
+
+        run_args(&path, &["undo"]).unwrap();
+
+        assert_eq!(State::load(&path).unwrap().fee, 500);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use crate::error::Error;
+use crate::state::State;
+use std::path::Path;
+
+/// Merge keys, images, UTXOs and history of another state file into the state
+///
+/// Items that are already known are skipped.
+/// Merged keys and images are disabled for spending.
+/// The transaction of the other state is ignored.
+pub fn import_state<P: AsRef<Path>>(state: &mut State, path: P) -> Result<(), Error> {
+    let other = State::load(path)?;
+
+    let (mut added, mut skipped) = (0, 0);
+    for (public_key, keypair) in other.active_keys.into_iter().chain(other.passive_keys) {
+        if state.active_keys.contains_key(&public_key)
+            || state.passive_keys.contains_key(&public_key)
+        {
+            skipped += 1;
+            continue;
+        }
+        // A known secret key replaces a watch-only key
+        state.watch_keys.remove(&public_key);
+        if let Some(origin) = other.key_origins.get(&public_key) {
+            state.key_origins.insert(public_key, origin.clone());
+        }
+        if let Some(label) = other.key_labels.get(&public_key) {
+            state.key_labels.insert(public_key, label.clone());
+        }
+        state.passive_keys.insert(public_key, keypair);
+        added += 1;
+    }
+    println!("Keys: {} added, {} skipped", added, skipped);
+
+    let (mut added, mut skipped) = (0, 0);
+    for public_key in other.watch_keys {
+        if state.active_keys.contains_key(&public_key)
+            || state.passive_keys.contains_key(&public_key)
+            || state.watch_keys.contains(&public_key)
+        {
+            skipped += 1;
+            continue;
+        }
+        if let Some(label) = other.key_labels.get(&public_key) {
+            state
+                .key_labels
+                .entry(public_key)
+                .or_insert_with(|| label.clone());
+        }
+        state.watch_keys.insert(public_key);
+        added += 1;
+    }
+    println!("Watch-only keys: {} added, {} skipped", added, skipped);
+
+    let (mut added, mut skipped) = (0, 0);
+    for (image, preimage) in other.active_images.into_iter().chain(other.passive_images) {
+        if state.active_images.contains_key(&image) || state.passive_images.contains_key(&image) {
+            skipped += 1;
+            continue;
+        }
+        // A known preimage replaces a watch-only image
+        state.watch_images.remove(&image);
+        if let Some(label) = other.image_labels.get(&image) {
+            state
+                .image_labels
+                .entry(image)
+                .or_insert_with(|| label.clone());
+        }
+        state.passive_images.insert(image, preimage);
+        added += 1;
+    }
+    println!("Images: {} added, {} skipped", added, skipped);
+
+    let (mut added, mut skipped) = (0, 0);
+    for image in other.watch_images {
+        if state.active_images.contains_key(&image)
+            || state.passive_images.contains_key(&image)
+            || state.watch_images.contains(&image)
+        {
+            skipped += 1;
+            continue;
+        }
+        if let Some(label) = other.image_labels.get(&image) {
+            state
+                .image_labels
+                .entry(image)
+                .or_insert_with(|| label.clone());
+        }
+        state.watch_images.insert(image);
+        added += 1;
+    }
+    println!("Watch-only images: {} added, {} skipped", added, skipped);
+
+    let (mut added, mut skipped) = (0, 0);
+    for utxo in other.utxos {
+        if state.utxos.iter().any(|x| x.outpoint == utxo.outpoint) {
+            skipped += 1;
+            continue;
+        }
+        if let Some(label) = other.utxo_labels.get(&utxo.outpoint) {
+            state
+                .utxo_labels
+                .entry(utxo.outpoint)
+                .or_insert_with(|| label.clone());
+        }
+        state.utxos.push(utxo);
+        added += 1;
+    }
+    println!("UTXOs: {} added, {} skipped", added, skipped);
+
+    // Keep history in sync, so rebuilding the UTXO set keeps merged UTXOs
+    for entry in other.history {
+        if !state.history.contains(&entry) {
+            state.history.push(entry);
+        }
+    }
+
+    Ok(())
+}
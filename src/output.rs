@@ -1,6 +1,9 @@
 use crate::error::Error;
-use crate::state::{Output, State};
+use crate::state::{Destination, Output, State};
+use crate::style;
 use crate::util;
+use itertools::Itertools;
+use miniscript::bitcoin::hashes::hex::FromHex;
 use miniscript::{bitcoin, Descriptor};
 
 pub fn add_output(
@@ -8,23 +11,267 @@ pub fn add_output(
     output_index: usize,
     descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
     value: u64,
+    weight: u32,
 ) -> Result<Option<Output>, Error> {
     util::verify_taproot(&descriptor)?;
 
-    if state.outputs.values().any(|o| o.value == 0) {
-        return Err(Error::OneZeroOutput);
+    let output = Output {
+        value,
+        weight,
+        pays_fee: false,
+        destination: Destination::Descriptor(descriptor),
+    };
+    insert_output(state, output_index, output)
+}
+
+pub fn add_address_output(
+    state: &mut State,
+    output_index: usize,
+    address: bitcoin::Address,
+    value: u64,
+    weight: u32,
+) -> Result<Option<Output>, Error> {
+    if !address.is_valid_for_network(state.network) {
+        return Err(Error::WrongNetwork);
     }
 
-    let output = Output { value, descriptor };
-    println!("New output #{}: {}", output_index, output);
+    let output = Output {
+        value,
+        weight,
+        pays_fee: false,
+        destination: Destination::Address(address),
+    };
+    insert_output(state, output_index, output)
+}
+
+pub fn add_script_output(
+    state: &mut State,
+    output_index: usize,
+    script_hex: &str,
+    value: u64,
+    weight: u32,
+) -> Result<Option<Output>, Error> {
+    let script_pubkey = bitcoin::Script::from(Vec::<u8>::from_hex(script_hex)?);
+
+    let output = Output {
+        value,
+        weight,
+        pays_fee: false,
+        destination: Destination::Script(script_pubkey),
+    };
+    insert_output(state, output_index, output)
+}
+
+/// Maximum size of OP_RETURN data that is standard
+const MAX_DATA_SIZE: usize = 80;
+
+pub fn add_data_output(
+    state: &mut State,
+    output_index: usize,
+    data_hex: &str,
+) -> Result<Option<Output>, Error> {
+    let data = Vec::<u8>::from_hex(data_hex)?;
+
+    if data.len() > MAX_DATA_SIZE {
+        return Err(Error::DataTooLong);
+    }
+
+    let output = Output {
+        value: 0,
+        weight: 1,
+        pays_fee: false,
+        destination: Destination::Data(data),
+    };
+    insert_output(state, output_index, output)
+}
+
+fn insert_output(
+    state: &mut State,
+    output_index: usize,
+    output: Output,
+) -> Result<Option<Output>, Error> {
+    if output.weight == 0 {
+        return Err(Error::ZeroWeight);
+    }
+    if output.weight != 1 && !output.receives_remaining() {
+        return Err(Error::WeightWithoutRemaining);
+    }
+    // Zero value marks the outputs that receive the remaining funds
+    let is_data = matches!(output.destination, Destination::Data(_));
+    let dust_limit = util::dust_limit(&output);
+    if !is_data && output.value != 0 && output.value < dust_limit {
+        return Err(Error::DustOutput(dust_limit));
+    }
+
+    println!(
+        "{}",
+        style::added(format_args!("New output #{}: {}", output_index, output))
+    );
     let old = state.outputs.insert(output_index, output);
 
     Ok(old)
 }
 
+/// Copy the given output into the next `count` free indices
+///
+/// Copies never pay the fee.
+///
+/// Returns the indices of the copies
+pub fn duplicate_output(
+    state: &mut State,
+    output_index: usize,
+    count: usize,
+) -> Result<Vec<usize>, Error> {
+    let source = state
+        .outputs
+        .get(&output_index)
+        .ok_or(Error::MissingOutput)?
+        .clone();
+
+    let mut new_indices = Vec::with_capacity(count);
+    let mut new_index = output_index + 1;
+    for _ in 0..count {
+        while state.outputs.contains_key(&new_index) {
+            new_index += 1;
+        }
+        let output = Output {
+            pays_fee: false,
+            ..source.clone()
+        };
+        insert_output(state, new_index, output)?;
+        new_indices.push(new_index);
+    }
+
+    Ok(new_indices)
+}
+
+/// Print where the given output pays to and how much it receives
+///
+/// Outputs that receive the remaining funds or pay the fee show their final value
+pub fn show_output(state: &State, output_index: usize) -> Result<(), Error> {
+    let output = state
+        .outputs
+        .get(&output_index)
+        .ok_or(Error::MissingOutput)?;
+    let script_pubkey = output.script_pubkey();
+
+    println!("Destination: {}", output.destination);
+    println!("Script pubkey: {:x}", script_pubkey);
+    match bitcoin::Address::from_script(&script_pubkey, state.network) {
+        Ok(address) => println!("Address: {}", address),
+        Err(_) => println!("Address: none"),
+    }
+
+    if output.receives_remaining() {
+        let value = util::get_remaining_funds(state)?
+            .into_iter()
+            .find(|(index, _)| *index == output_index)
+            .map(|(_, value)| value)
+            .expect("output receives remaining funds");
+        println!("Value: {} sat (remaining funds)", value);
+    } else if let Some((_, value)) =
+        util::get_fee_output_value(state)?.filter(|(index, _)| *index == output_index)
+    {
+        println!("Value: {} sat (after paying the fee)", value);
+    } else {
+        println!("Value: {} sat", output.value);
+    }
+
+    Ok(())
+}
+
 pub fn delete_output(state: &mut State, output_index: usize) -> Result<Output, Error> {
     state
         .outputs
         .remove(&output_index)
         .ok_or(Error::MissingOutput)
 }
+
+/// Move output to another index
+///
+/// If the other index is occupied, then both outputs swap places
+pub fn move_output(state: &mut State, from: usize, to: usize) -> Result<(), Error> {
+    let output = state.outputs.remove(&from).ok_or(Error::MissingOutput)?;
+
+    if let Some(occupant) = state.outputs.insert(to, output) {
+        state.outputs.insert(from, occupant);
+    }
+
+    Ok(())
+}
+
+pub fn reorder_outputs(state: &mut State, order: &[usize]) -> Result<(), Error> {
+    let is_permutation = order.len() == state.outputs.len()
+        && order.iter().all_unique()
+        && order.iter().all(|index| state.outputs.contains_key(index));
+    if !is_permutation {
+        return Err(Error::InvalidOutputOrder);
+    }
+
+    let mut reordered = Vec::with_capacity(order.len());
+    for old_index in order {
+        let output = state.outputs.remove(old_index).expect("checked above");
+        reordered.push(output);
+    }
+
+    for (new_index, output) in reordered.into_iter().enumerate() {
+        state.outputs.insert(new_index, output);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input;
+
+    fn descriptor() -> Descriptor<bitcoin::XOnlyPublicKey> {
+        "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn second_zero_value_output_shares_remaining_funds() {
+        let mut state = State::new();
+        let outpoint = bitcoin::OutPoint::default();
+        input::add_from_outpoint(&mut state, 0, outpoint, 41_000, descriptor()).unwrap();
+        state.fee = 1_000;
+
+        add_output(&mut state, 0, descriptor(), 0, 1).unwrap();
+        add_output(&mut state, 1, descriptor(), 0, 3).unwrap();
+
+        assert_eq!(
+            util::get_remaining_funds(&state).unwrap(),
+            vec![(0, 10_000), (1, 30_000)]
+        );
+    }
+
+    #[test]
+    fn dust_limit_depends_on_script() {
+        let mut state = State::new();
+        add_output(&mut state, 0, descriptor(), 330, 1).unwrap();
+
+        // P2PKH outputs have a higher dust limit than taproot outputs
+        let address: bitcoin::Address = "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn".parse().unwrap();
+        assert!(matches!(
+            add_address_output(&mut state, 1, address.clone(), 500, 1),
+            Err(Error::DustOutput(546))
+        ));
+        add_address_output(&mut state, 1, address, 546, 1).unwrap();
+    }
+
+    #[test]
+    fn weight_requires_zero_value() {
+        let mut state = State::new();
+        assert!(matches!(
+            add_output(&mut state, 0, descriptor(), 1_000, 2),
+            Err(Error::WeightWithoutRemaining)
+        ));
+        assert!(matches!(
+            add_output(&mut state, 0, descriptor(), 0, 0),
+            Err(Error::ZeroWeight)
+        ));
+    }
+}
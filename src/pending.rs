@@ -0,0 +1,56 @@
+use crate::error::Error;
+use crate::state::Spend;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Path of the pending broadcast marker of the given state file
+pub fn pending_path(path: &Path) -> PathBuf {
+    let mut pending_path = path.as_os_str().to_owned();
+    pending_path.push(".pending");
+    PathBuf::from(pending_path)
+}
+
+/// Remember the spend that is about to be broadcast,
+/// so it can be finalized if tappy stops before saving the state
+pub fn mark(path: &Path, spend: &Spend) -> Result<(), Error> {
+    let file = File::create(pending_path(path))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, spend)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Return the spend of the pending broadcast marker, if there is one
+pub fn load(path: &Path) -> Result<Option<Spend>, Error> {
+    match fs::read(pending_path(path)) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Delete the pending broadcast marker of the given state file, if there is one
+pub fn clear(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(pending_path(path)) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn missing_marker_is_none() {
+        let dir = test_dir("marker");
+        let path = dir.join("state.json");
+
+        assert!(load(&path).unwrap().is_none());
+        clear(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use crate::error::Error;
+use crate::util;
+use miniscript::bitcoin;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Command line client of Bitcoin Core
+const BITCOIN_CLI: &str = "bitcoin-cli";
+
+/// Error message of Bitcoin Core for a transaction that is neither in the mempool nor in a block
+///
+/// Without -txindex, the message for unknown transactions differs and is not conclusive
+pub const UNKNOWN_TRANSACTION: &str = "No such mempool or blockchain transaction";
+
+static COOKIE: OnceLock<Option<PathBuf>> = OnceLock::new();
+static CONNECT: OnceLock<Option<String>> = OnceLock::new();
+
+/// Set the cookie file and the node address that are passed to the command line client
+pub fn init(cookie: Option<PathBuf>, connect: Option<String>) {
+    let _ = COOKIE.set(cookie);
+    let _ = CONNECT.set(connect);
+}
+
+/// Options that are passed to the command line client before the method
+fn client_options() -> Vec<String> {
+    let mut options = Vec::new();
+
+    if let Some(cookie) = COOKIE.get().and_then(Option::as_ref) {
+        options.push(format!("-rpccookiefile={}", cookie.display()));
+    }
+    if let Some(connect) = CONNECT.get().and_then(Option::as_ref) {
+        options.push(format!("-rpcconnect={}", connect));
+    }
+
+    options
+}
+
+/// Return the command line that calls Bitcoin Core like [`call`] does
+pub fn command_line(method: &str, args: &[&str]) -> String {
+    std::iter::once(BITCOIN_CLI.to_string())
+        .chain(client_options())
+        .chain(std::iter::once(method.to_string()))
+        .chain(args.iter().map(|arg| arg.to_string()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Call Bitcoin Core via its command line client and return the trimmed output
+pub fn call(method: &str, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new(BITCOIN_CLI)
+        .args(client_options())
+        .arg(method)
+        .args(args)
+        .output()
+        .map_err(|error| Error::Rpc(format!("Cannot run {}: {}", BITCOIN_CLI, error)))?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(Error::Rpc(message));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+pub fn get_transaction(txid: bitcoin::Txid) -> Result<bitcoin::Transaction, Error> {
+    let tx_hex = call("getrawtransaction", &[&txid.to_string()])?;
+    util::parse_transaction(&tx_hex)
+}
+
+/// Broadcast a raw transaction and return its txid
+pub fn send_transaction(tx_hex: &str) -> Result<bitcoin::Txid, Error> {
+    let txid = call("sendrawtransaction", &[tx_hex])?;
+    txid.parse()
+        .map_err(|_| Error::Rpc(format!("Unexpected txid: {}", txid)))
+}
+
+/// Estimate the feerate in sat / vB for confirmation within the given number of blocks
+///
+/// Returns `None` if the node has no estimate, for instance due to too little data
+pub fn estimate_feerate(conf_target: u16) -> Result<Option<f64>, Error> {
+    let response: Value =
+        serde_json::from_str(&call("estimatesmartfee", &[&conf_target.to_string()])?)?;
+    // Feerate is given in BTC / kvB
+    let feerate = response["feerate"]
+        .as_f64()
+        .map(|btc_per_kvb| btc_per_kvb * 100_000_000.0 / 1000.0);
+
+    Ok(feerate)
+}
+
+/// Find the unspent outputs of the given address in the UTXO set of the node
+pub fn scan_address(address: &bitcoin::Address) -> Result<Vec<(bitcoin::OutPoint, u64)>, Error> {
+    let descriptors = format!("[\"addr({})\"]", address);
+    let response: Value = serde_json::from_str(&call("scantxoutset", &["start", &descriptors])?)?;
+    let unspents = response["unspents"]
+        .as_array()
+        .ok_or_else(|| Error::Rpc("Missing unspents in scantxoutset response".to_string()))?;
+    let mut found = Vec::new();
+
+    for unspent in unspents {
+        let unexpected = || Error::Rpc(format!("Unexpected unspent output: {}", unspent));
+        let txid = unspent["txid"]
+            .as_str()
+            .and_then(|txid| txid.parse().ok())
+            .ok_or_else(unexpected)?;
+        let vout = unspent["vout"]
+            .as_u64()
+            .and_then(|vout| u32::try_from(vout).ok())
+            .ok_or_else(unexpected)?;
+        let amount = unspent["amount"]
+            .as_f64()
+            .and_then(|amount| bitcoin::Amount::from_btc(amount).ok())
+            .ok_or_else(unexpected)?;
+
+        found.push((bitcoin::OutPoint { txid, vout }, amount.to_sat()));
+    }
+
+    Ok(found)
+}
@@ -1,23 +1,34 @@
 use crate::error::Error;
-use crate::state::State;
+use crate::state::{Output, Spend, State};
+use crate::style;
 use crate::util;
 use itertools::Itertools;
-use miniscript::bitcoin::hashes::sha256;
+use miniscript::bitcoin::hashes::{hash160, ripemd160, sha256, Hash};
 use miniscript::bitcoin::psbt::serialize::Serialize;
-use miniscript::bitcoin::psbt::Prevouts;
+use miniscript::bitcoin::psbt::{Prevouts, Psbt};
 use miniscript::bitcoin::schnorr::TapTweak;
 use miniscript::bitcoin::secp256k1::{All, Message, Secp256k1};
 use miniscript::bitcoin::util::sighash::SighashCache;
-use miniscript::bitcoin::util::taproot::{TapBranchHash, TapLeafHash, TapSighashHash};
+use miniscript::bitcoin::util::taproot::{LeafVersion, TapBranchHash, TapLeafHash, TapSighashHash};
 use miniscript::bitcoin::{LockTime, PackedLockTime, SchnorrSighashType, Sequence, Witness};
-use miniscript::{bitcoin, Descriptor, MiniscriptKey, Preimage32, Satisfier, ToPublicKey};
+use miniscript::hash256;
+use miniscript::interpreter::Interpreter;
+use miniscript::{
+    bitcoin, Descriptor, ForEachKey, Miniscript, MiniscriptKey, Preimage32, Satisfier, Tap,
+    Terminal, ToPublicKey,
+};
 use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::rc::Rc;
 
-pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
+/// Construct the unsigned transaction of the state
+///
+/// Returns the transaction and the outputs that its inputs spend
+fn get_unsigned_transaction(
+    state: &State,
+) -> Result<(bitcoin::Transaction, Vec<&bitcoin::TxOut>), Error> {
     let mut spending_inputs = Vec::new();
     let mut receiving_outputs = Vec::new();
     let mut prevouts = Vec::new();
@@ -25,7 +36,7 @@ pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
     // Add unsigned inputs
     for (expected_index, input_index) in state.inputs.keys().sorted().enumerate() {
         if expected_index != *input_index {
-            return Err(Error::MissingInput);
+            return Err(Error::MissingInputIndex(expected_index));
         }
 
         let input = &state.inputs[input_index];
@@ -43,33 +54,82 @@ pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
     // Add outputs
     for (expected_index, output_index) in state.outputs.keys().sorted().enumerate() {
         if expected_index != *output_index {
-            return Err(Error::MissingOutput);
+            return Err(Error::MissingOutputIndex(expected_index));
         }
 
         let output = &state.outputs[output_index];
         let txout = bitcoin::TxOut {
             value: output.value,
-            script_pubkey: output.descriptor.script_pubkey(),
+            script_pubkey: output.script_pubkey(),
         };
         receiving_outputs.push(txout);
     }
 
-    // Assign remaining input funds to the remaining output (if it exists)
-    if let Some((output_index, value)) = util::get_remaining_funds(state)? {
+    // Assign remaining input funds to the outputs that receive them
+    for (output_index, value) in util::get_remaining_funds(state)? {
+        receiving_outputs[output_index].value = value;
+    }
+    // Subtract the fee from the output that pays it
+    if let Some((output_index, value)) = util::get_fee_output_value(state)? {
         receiving_outputs[output_index].value = value;
     }
 
     // Construct unsigned transaction
-    let mut spending_tx = bitcoin::Transaction {
-        version: 2,
+    let spending_tx = bitcoin::Transaction {
+        version: state.tx_version,
         lock_time: PackedLockTime(state.locktime.to_consensus_u32()),
         input: spending_inputs,
         output: receiving_outputs,
     };
 
+    Ok((spending_tx, prevouts))
+}
+
+/// Remember the transaction of the state as spent, for finalization
+///
+/// Witnesses do not change the txid, so the transaction may be unsigned
+pub fn record_spend(state: &mut State, transaction: bitcoin::Transaction) {
+    let spent = state
+        .inputs
+        .iter()
+        .sorted_by_key(|(index, _)| **index)
+        .map(|(_, input)| input.utxo.clone())
+        .collect();
+    let outputs = state
+        .outputs
+        .iter()
+        .sorted_by_key(|(index, _)| **index)
+        .zip(&transaction.output)
+        .map(|((_, output), txout)| Output {
+            value: txout.value,
+            ..output.clone()
+        })
+        .collect();
+
+    state.last_spend = Some(Spend {
+        transaction,
+        spent,
+        outputs,
+    });
+}
+
+/// Sign the transaction of the state and return its hex, fee, vsize
+/// and the indices of the inputs that stay unsigned
+///
+/// If `partial` is set, inputs that cannot be satisfied get an empty witness
+/// instead of failing the whole transaction.
+/// Unless skipped, each witness is checked against its spent output before returning
+pub fn get_raw_transaction(
+    state: &mut State,
+    skip_check: bool,
+    partial: bool,
+) -> Result<(String, u64, usize, Vec<usize>), Error> {
+    let (mut spending_tx, prevouts) = get_unsigned_transaction(state)?;
+
     let secp = Secp256k1::new();
     let cache = Rc::new(RefCell::new(SighashCache::new(&spending_tx)));
     let mut witnesses = Vec::new();
+    let mut unsigned = Vec::new();
 
     // Sign inputs
     for input_index in state.inputs.keys().sorted() {
@@ -85,6 +145,15 @@ pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
             _ => return Err(Error::OnlyTaproot),
         };
 
+        // SIGHASH_SINGLE signs the output at the same index as the input
+        let is_single = matches!(
+            input.sighash,
+            SchnorrSighashType::Single | SchnorrSighashType::SinglePlusAnyoneCanPay
+        );
+        if is_single && *input_index >= spending_tx.output.len() {
+            return Err(Error::SingleWithoutOutput(*input_index));
+        }
+
         let satisfier = DynamicSigner {
             active_keys: &state.active_keys,
             active_images: &state.active_images,
@@ -93,12 +162,27 @@ pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
             input_index: *input_index,
             prevouts: Prevouts::All(&prevouts),
             locktime: state.locktime,
-            sequence: state.inputs[input_index].sequence,
-            sighash_type: SchnorrSighashType::All,
+            sequence: input.sequence,
+            sighash_type: input.sighash,
             cache: cache.clone(),
             secp: &secp,
         };
-        let (witness, _script_sig) = input.utxo.descriptor.get_satisfaction(satisfier)?;
+        let (witness, _script_sig) = match input.utxo.descriptor.get_satisfaction(satisfier) {
+            Ok(satisfaction) => satisfaction,
+            Err(_) if partial => {
+                unsigned.push(*input_index);
+                (Vec::new(), bitcoin::Script::new())
+            }
+            Err(error) => {
+                if let Some(image) = find_watch_image(&input.utxo.descriptor, state) {
+                    return Err(Error::WatchOnlyImage(image));
+                }
+                if let Some(key) = find_watch_key(&input.utxo.descriptor, state) {
+                    return Err(Error::WatchOnlyKey(key));
+                }
+                return Err(error.into());
+            }
+        };
         witnesses.push(Witness::from_vec(witness));
     }
 
@@ -108,8 +192,15 @@ pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
         spending_tx.input[input_index].witness = witness;
     }
 
-    // Compute feerate (includes witness)
-    let feerate = state.fee as f64 / spending_tx.vsize() as f64;
+    if !skip_check {
+        check_witnesses(&spending_tx, &prevouts, &unsigned, &secp)?;
+    }
+
+    // Compute the actual fee,
+    // which differs from the state fee if excess funds are burned
+    let input_funds = prevouts.iter().fold(0, |x, o| x + o.value);
+    let output_funds = spending_tx.output.iter().fold(0, |x, o| x + o.value);
+    let fee = input_funds.saturating_sub(output_funds);
 
     // Serialize transaction as hex
     let tx_hex = spending_tx
@@ -118,7 +209,182 @@ pub fn get_raw_transaction(state: &mut State) -> Result<(String, f64), Error> {
         .map(|b| format!("{:02x}", b))
         .collect::<String>();
 
-    Ok((tx_hex, feerate))
+    record_spend(state, spending_tx.clone());
+
+    Ok((tx_hex, fee, spending_tx.vsize(), unsigned))
+}
+
+/// Run each input's witness through the miniscript interpreter,
+/// which verifies signatures, hash preimages and timelocks
+///
+/// The given unsigned inputs are skipped
+fn check_witnesses(
+    tx: &bitcoin::Transaction,
+    spent_outputs: &[&bitcoin::TxOut],
+    unsigned: &[usize],
+    secp: &Secp256k1<All>,
+) -> Result<(), Error> {
+    let prevouts = Prevouts::All(spent_outputs);
+
+    for (input_index, txin) in tx.input.iter().enumerate() {
+        if unsigned.contains(&input_index) {
+            continue;
+        }
+        let interpreter = Interpreter::from_txdata(
+            &spent_outputs[input_index].script_pubkey,
+            &txin.script_sig,
+            &txin.witness,
+            txin.sequence,
+            tx.lock_time.into(),
+        )
+        .map_err(|error| Error::WitnessCheck(input_index, error))?;
+
+        for satisfied in interpreter.iter(secp, tx, input_index, &prevouts) {
+            satisfied.map_err(|error| Error::WitnessCheck(input_index, error))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Construct the unsigned transaction of the state as PSBT
+///
+/// Inputs include the spent output, the internal key, the merkle root and all leaf scripts
+pub fn get_psbt(state: &State) -> Result<Psbt, Error> {
+    let (spending_tx, _) = get_unsigned_transaction(state)?;
+    let mut psbt = Psbt::from_unsigned_tx(spending_tx)?;
+
+    for (input_index, psbt_input) in psbt.inputs.iter_mut().enumerate() {
+        let utxo = &state.inputs[&input_index].utxo;
+        let tr = match &utxo.descriptor {
+            Descriptor::Tr(tr) => tr,
+            _ => return Err(Error::OnlyTaproot),
+        };
+        let info = tr.spend_info();
+
+        psbt_input.witness_utxo = Some(utxo.output.clone());
+        psbt_input.sighash_type = Some(state.inputs[&input_index].sighash.into());
+        psbt_input.tap_internal_key = Some(info.internal_key());
+        psbt_input.tap_merkle_root = info.merkle_root();
+
+        for (_, ms) in tr.iter_scripts() {
+            let script_ver = (ms.encode(), LeafVersion::TapScript);
+            let control_block = info
+                .control_block(&script_ver)
+                .expect("leaf is part of the tree");
+            psbt_input.tap_scripts.insert(control_block, script_ver);
+        }
+    }
+
+    Ok(psbt)
+}
+
+/// Sign the inputs of a PSBT with the enabled keys
+///
+/// Adds a key spend signature if the internal key is enabled
+/// and a script spend signature for every enabled key in every leaf script
+///
+/// Returns the number of added signatures
+pub fn sign_psbt(state: &State, psbt: &mut Psbt) -> Result<usize, Error> {
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .map(|psbt_input| psbt_input.witness_utxo.clone())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Error::MissingWitnessUtxo)?;
+    let secp = Secp256k1::new();
+    let cache = Rc::new(RefCell::new(SighashCache::new(&psbt.unsigned_tx)));
+    let mut key_sigs = Vec::new();
+    let mut script_sigs = Vec::new();
+
+    for (input_index, psbt_input) in psbt.inputs.iter().enumerate() {
+        let internal_key = match psbt_input.tap_internal_key {
+            Some(internal_key) => internal_key,
+            None => continue,
+        };
+        let signer = DynamicSigner {
+            active_keys: &state.active_keys,
+            active_images: &state.active_images,
+            internal_key: internal_key.to_public_key(),
+            merkle_root: psbt_input.tap_merkle_root,
+            input_index,
+            prevouts: Prevouts::All(&prevouts),
+            locktime: psbt.unsigned_tx.lock_time.into(),
+            sequence: psbt.unsigned_tx.input[input_index].sequence,
+            sighash_type: psbt_input.schnorr_hash_ty()?,
+            cache: cache.clone(),
+            secp: &secp,
+        };
+
+        if state
+            .active_keys
+            .contains_key(&internal_key.to_public_key())
+        {
+            if let Some(sig) =
+                Satisfier::<bitcoin::XOnlyPublicKey>::lookup_tap_key_spend_sig(&signer)
+            {
+                key_sigs.push((input_index, sig));
+            }
+        }
+
+        for (script, version) in psbt_input.tap_scripts.values() {
+            let leaf_hash = TapLeafHash::from_script(script, *version);
+            let ms = Miniscript::<bitcoin::XOnlyPublicKey, Tap>::parse_insane(script)?;
+
+            for pk in ms.iter_pk() {
+                if !state.active_keys.contains_key(&pk.to_public_key()) {
+                    continue;
+                }
+                if let Some(sig) = signer.lookup_tap_leaf_script_sig(&pk, &leaf_hash) {
+                    script_sigs.push((input_index, (pk, leaf_hash), sig));
+                }
+            }
+        }
+    }
+
+    // Cannot be done in previous loop due to borrowing issue
+    let signatures = key_sigs.len() + script_sigs.len();
+    for (input_index, sig) in key_sigs {
+        psbt.inputs[input_index].tap_key_sig = Some(sig);
+    }
+    for (input_index, key, sig) in script_sigs {
+        psbt.inputs[input_index].tap_script_sigs.insert(key, sig);
+    }
+
+    Ok(signatures)
+}
+
+/// Find a watch-only image that is used in the descriptor
+fn find_watch_image(
+    descriptor: &Descriptor<bitcoin::XOnlyPublicKey>,
+    state: &State,
+) -> Option<sha256::Hash> {
+    let tr = match descriptor {
+        Descriptor::Tr(tr) => tr,
+        _ => return None,
+    };
+
+    tr.iter_scripts()
+        .flat_map(|(_, ms)| ms.iter())
+        .find_map(|node| match &node.node {
+            Terminal::Sha256(image) if state.watch_images.contains(image) => Some(*image),
+            _ => None,
+        })
+}
+
+/// Find a watch-only key that is used in the descriptor
+fn find_watch_key(
+    descriptor: &Descriptor<bitcoin::XOnlyPublicKey>,
+    state: &State,
+) -> Option<bitcoin::XOnlyPublicKey> {
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(*key);
+        true
+    });
+
+    keys.into_iter()
+        .find(|key| state.watch_keys.contains(&key.to_public_key()))
 }
 
 struct DynamicSigner<'a, T: Deref<Target = bitcoin::Transaction>, O: Borrow<bitcoin::TxOut>> {
@@ -144,7 +410,10 @@ where
         match self.active_keys.get(&pk) {
             Some(keypair) => Some(keypair),
             None => {
-                println!("Unknown key: {}", util::into_xonly(pk));
+                println!(
+                    "{}",
+                    style::error(format_args!("Unknown key: {}", util::into_xonly(pk)))
+                );
                 None
             }
         }
@@ -163,6 +432,17 @@ where
             hash_ty: self.sighash_type,
         }
     }
+
+    /// Find an enabled preimage whose digest matches
+    ///
+    /// Preimages are keyed by their SHA-256 image,
+    /// so other hash types require a search
+    fn find_preimage<F>(&self, matches: F) -> Option<Preimage32>
+    where
+        F: Fn(&Preimage32) -> bool,
+    {
+        self.active_images.values().find(|p| matches(p)).copied()
+    }
 }
 
 impl<'a, Pk, T, O> Satisfier<Pk> for DynamicSigner<'a, T, O>
@@ -172,7 +452,8 @@ where
     O: Borrow<bitcoin::TxOut>,
 {
     fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
-        let internal_pair = self.get_keypair(self.internal_key)?;
+        // Without the internal key, fall back to the script path without complaint
+        let internal_pair = self.active_keys.get(&self.internal_key)?;
         let output_pair = internal_pair
             .tap_tweak(self.secp, self.merkle_root)
             .to_inner();
@@ -220,6 +501,21 @@ where
         self.active_images.get(image.as_ref()).copied()
     }
 
+    fn lookup_hash256(&self, image: &Pk::Hash256) -> Option<Preimage32> {
+        let image = Pk::to_hash256(image);
+        self.find_preimage(|preimage| hash256::Hash::hash(preimage) == image)
+    }
+
+    fn lookup_ripemd160(&self, image: &Pk::Ripemd160) -> Option<Preimage32> {
+        let image = Pk::to_ripemd160(image);
+        self.find_preimage(|preimage| ripemd160::Hash::hash(preimage) == image)
+    }
+
+    fn lookup_hash160(&self, image: &Pk::Hash160) -> Option<Preimage32> {
+        let image = Pk::to_hash160(image);
+        self.find_preimage(|preimage| hash160::Hash::hash(preimage) == image)
+    }
+
     fn check_older(&self, sequence: Sequence) -> bool {
         <Sequence as Satisfier<Pk>>::check_older(&self.sequence, sequence)
     }
@@ -228,3 +524,76 @@ where
         <LockTime as Satisfier<Pk>>::check_after(&self.locktime, locktime)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enable_new_key, new_descriptor};
+    use crate::{input, output};
+
+    fn outpoint(vout: u32) -> bitcoin::OutPoint {
+        bitcoin::OutPoint {
+            vout,
+            ..Default::default()
+        }
+    }
+
+    /// Sign input 0 with the given sighash type, then sign again after appending input 1
+    /// and check whether the first signature of input 0 is still valid
+    fn first_signature_survives(sighash: SchnorrSighashType) -> bool {
+        let mut state = State::new();
+        let descriptor = new_descriptor(&mut state);
+        input::add_from_outpoint(&mut state, 0, outpoint(0), 100_000, descriptor.clone()).unwrap();
+        input::update_sighash(&mut state, 0, sighash).unwrap();
+        output::add_output(&mut state, 0, descriptor, 50_000, 1).unwrap();
+        let (first_hex, ..) = get_raw_transaction(&mut state, false, false).unwrap();
+        let first_tx = util::parse_transaction(&first_hex).unwrap();
+
+        let other_descriptor = new_descriptor(&mut state);
+        input::add_from_outpoint(&mut state, 1, outpoint(1), 20_000, other_descriptor).unwrap();
+        let (second_hex, ..) = get_raw_transaction(&mut state, false, false).unwrap();
+        let mut second_tx = util::parse_transaction(&second_hex).unwrap();
+        second_tx.input[0].witness = first_tx.input[0].witness.clone();
+
+        let prevouts: Vec<&bitcoin::TxOut> = [0, 1]
+            .iter()
+            .map(|index| &state.inputs[index].utxo.output)
+            .collect();
+        check_witnesses(&second_tx, &prevouts, &[], &Secp256k1::new()).is_ok()
+    }
+
+    #[test]
+    fn key_path_and_script_path_inputs_in_one_spend() {
+        let mut state = State::new();
+        let key = enable_new_key(&mut state);
+        let key_path = Descriptor::new_tr(key, None).unwrap();
+        // The internal key is not in the state, so only the leaf can sign
+        let script_path: Descriptor<bitcoin::XOnlyPublicKey> = format!(
+            "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f,pk({}))",
+            key
+        )
+        .parse()
+        .unwrap();
+        input::add_from_outpoint(&mut state, 0, outpoint(0), 50_000, key_path.clone()).unwrap();
+        input::add_from_outpoint(&mut state, 1, outpoint(1), 50_000, script_path).unwrap();
+        output::add_output(&mut state, 0, key_path, 0, 1).unwrap();
+
+        let (tx_hex, ..) = get_raw_transaction(&mut state, false, false).unwrap();
+        let tx = util::parse_transaction(&tx_hex).unwrap();
+
+        assert_eq!(tx.input[0].witness.len(), 1);
+        assert_eq!(tx.input[1].witness.len(), 3);
+    }
+
+    #[test]
+    fn anyonecanpay_signature_survives_added_input() {
+        assert!(first_signature_survives(
+            SchnorrSighashType::AllPlusAnyoneCanPay
+        ));
+    }
+
+    #[test]
+    fn all_signature_breaks_with_added_input() {
+        assert!(!first_signature_survives(SchnorrSighashType::All));
+    }
+}
@@ -1,43 +1,157 @@
 use crate::error::Error;
+use crate::util;
 use itertools::Itertools;
-use miniscript::bitcoin::hashes::sha256;
-use miniscript::bitcoin::{LockTime, Sequence};
+use miniscript::bitcoin::hashes::hex::ToHex;
+use miniscript::bitcoin::hashes::{sha256, Hash};
+use miniscript::bitcoin::util::bip32::KeySource;
+use miniscript::bitcoin::{LockTime, SchnorrSighashType, Sequence};
 use miniscript::Descriptor;
 use miniscript::{bitcoin, Preimage32};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Version of the state file format that this binary writes
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Migrations of the raw state file
+///
+/// The migration at index `i` upgrades a file from version `i` to version `i + 1`
+const MIGRATIONS: [fn(&mut Map<String, Value>); CURRENT_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Add a field to every stored output: of the transaction and of the last spend
+fn add_output_field(state: &mut Map<String, Value>, name: &str, value: Value) {
+    let add_field = |outputs: Option<&mut Value>| {
+        for output in outputs.into_iter().flat_map(|outputs| match outputs {
+            Value::Object(outputs) => outputs.values_mut().collect::<Vec<_>>(),
+            Value::Array(outputs) => outputs.iter_mut().collect(),
+            _ => Vec::new(),
+        }) {
+            if let Value::Object(output) = output {
+                output.entry(name).or_insert_with(|| value.clone());
+            }
+        }
+    };
+    add_field(state.get_mut("outputs"));
+    add_field(
+        state
+            .get_mut("last_spend")
+            .and_then(|last_spend| last_spend.get_mut("outputs")),
+    );
+}
+
+fn migrate_v0_to_v1(state: &mut Map<String, Value>) {
+    for field in ["history", "watch_keys", "watch_images"] {
+        state
+            .entry(field)
+            .or_insert_with(|| Value::Array(Vec::new()));
+    }
+    for field in [
+        "key_origins",
+        "key_labels",
+        "image_labels",
+        "utxo_labels",
+        "templates",
+    ] {
+        state
+            .entry(field)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    state
+        .entry("network")
+        .or_insert_with(|| Value::from("regtest"));
+    state.entry("tx_version").or_insert_with(|| Value::from(2));
+    state.entry("feerate").or_insert(Value::Null);
+    state.entry("last_spend").or_insert(Value::Null);
+
+    if let Some(inputs) = state.get_mut("inputs").and_then(Value::as_object_mut) {
+        for input in inputs.values_mut().filter_map(Value::as_object_mut) {
+            input
+                .entry("sighash")
+                .or_insert_with(|| Value::from("SIGHASH_ALL"));
+        }
+    }
+    add_output_field(state, "weight", Value::from(1));
+    add_output_field(state, "pays_fee", Value::Bool(false));
+
+    if let Some(history) = state.get_mut("history").and_then(Value::as_array_mut) {
+        for entry in history.iter_mut().filter_map(Value::as_object_mut) {
+            entry.entry("spend").or_insert(Value::Null);
+        }
+    }
+
+    // A single inbound address became a map of indexed addresses
+    let mut addresses = Map::new();
+    match state.remove("inbound_address") {
+        Some(Value::Null) | None => {}
+        Some(descriptor) => {
+            addresses.insert("0".to_string(), descriptor);
+        }
+    }
+    state
+        .entry("inbound_addresses")
+        .or_insert(Value::Object(addresses));
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct State {
+    pub version: u32,
+    pub network: bitcoin::Network,
     pub passive_keys: HashMap<bitcoin::PublicKey, bitcoin::KeyPair>,
     pub active_keys: HashMap<bitcoin::PublicKey, bitcoin::KeyPair>,
+    pub key_origins: HashMap<bitcoin::PublicKey, KeySource>,
+    pub key_labels: HashMap<bitcoin::PublicKey, String>,
+    /// Public keys whose secret key is unknown
+    pub watch_keys: HashSet<bitcoin::PublicKey>,
     pub passive_images: HashMap<sha256::Hash, Preimage32>,
     pub active_images: HashMap<sha256::Hash, Preimage32>,
-    pub inbound_address: Option<Descriptor<bitcoin::XOnlyPublicKey>>,
+    pub image_labels: HashMap<sha256::Hash, String>,
+    /// Images whose preimage is unknown
+    pub watch_images: HashSet<sha256::Hash>,
+    /// Descriptors that wait for funding
+    pub inbound_addresses: HashMap<usize, Descriptor<bitcoin::XOnlyPublicKey>>,
     pub utxos: Vec<Utxo>,
+    pub utxo_labels: HashMap<bitcoin::OutPoint, String>,
     pub inputs: HashMap<usize, Input>,
     pub outputs: HashMap<usize, Output>,
+    pub tx_version: i32,
     pub locktime: LockTime,
     pub fee: u64,
+    /// Target feerate in sat / vB that determines the fee during spending
+    pub feerate: Option<f64>,
+    /// Transaction of the most recent spend
+    pub last_spend: Option<Spend>,
+    pub history: Vec<HistoryEntry>,
+    /// Descriptors with placeholders `$0`, `$1`, ... by name
+    pub templates: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Input {
     pub utxo: Utxo,
     pub sequence: Sequence,
+    pub sighash: SchnorrSighashType,
 }
 
 impl fmt::Display for Input {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.utxo)?;
 
-        if self.sequence != Sequence::MAX {
-            let relative_timelock = self.sequence.0;
-            write!(f, " +{} blocks", relative_timelock)?;
+        if let Some(description) = util::describe_sequence(self.sequence) {
+            write!(f, " {}", description)?;
+        } else if self.sequence == Sequence::ENABLE_RBF_NO_LOCKTIME {
+            write!(f, " (RBF)")?;
+        }
+        if !util::is_clean_sequence(self.sequence) {
+            write!(f, " (sequence {:#010x})", self.sequence.0)?;
+        }
+
+        if self.sighash != SchnorrSighashType::All {
+            write!(f, " [{}]", self.sighash)?;
         }
 
         Ok(())
@@ -64,49 +178,244 @@ impl fmt::Display for Utxo {
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Output {
     pub value: u64,
-    pub descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    /// Share of the remaining funds, relative to the other outputs that receive them
+    pub weight: u32,
+    /// The fee is subtracted from the value of this output
+    pub pays_fee: bool,
+    #[serde(flatten)]
+    pub destination: Destination,
+}
+
+impl Output {
+    pub fn script_pubkey(&self) -> bitcoin::Script {
+        match &self.destination {
+            Destination::Descriptor(descriptor) => descriptor.script_pubkey(),
+            Destination::Address(address) => address.script_pubkey(),
+            Destination::Script(script_pubkey) => script_pubkey.clone(),
+            Destination::Data(data) => bitcoin::Script::new_op_return(data),
+        }
+    }
+
+    /// Return true if the output receives the remaining input funds
+    ///
+    /// These are zero-value outputs, except for data outputs
+    pub fn receives_remaining(&self) -> bool {
+        self.value == 0 && !matches!(self.destination, Destination::Data(_))
+    }
 }
 
 impl fmt::Display for Output {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {} sat", self.descriptor, self.value)
+        write!(f, "{} {} sat", self.destination, self.value)?;
+        if self.receives_remaining() && self.weight != 1 {
+            write!(f, " (weight {})", self.weight)?;
+        }
+        if self.pays_fee {
+            write!(f, " (pays fee)")?;
+        }
+        Ok(())
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
+pub enum Destination {
+    Descriptor(Descriptor<bitcoin::XOnlyPublicKey>),
+    Address(bitcoin::Address),
+    /// Raw script pubkey
+    Script(bitcoin::Script),
+    /// OP_RETURN with the given data
+    Data(#[serde(with = "hex_bytes")] Vec<u8>),
+}
+
+/// Serialize bytes as hex string
+mod hex_bytes {
+    use miniscript::bitcoin::hashes::hex::{FromHex, ToHex};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&bytes.to_hex())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Vec::from_hex(&s).map_err(de::Error::custom)
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Descriptor(descriptor) => write!(f, "{}", descriptor),
+            Destination::Address(address) => write!(f, "{}", address),
+            Destination::Script(script_pubkey) => write!(f, "script {:x}", script_pubkey),
+            Destination::Data(data) => write!(f, "OP_RETURN {}", data.to_hex()),
+        }
+    }
+}
+
+/// Transaction as it was spent, which finalization turns into UTXOs
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Spend {
+    pub transaction: bitcoin::Transaction,
+    /// UTXOs that the transaction spends, in input order
+    pub spent: Vec<Utxo>,
+    /// Outputs with the remaining funds assigned, in output order
+    pub outputs: Vec<Output>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct HistoryEntry {
+    pub txid: bitcoin::Txid,
+    pub spent: Vec<bitcoin::OutPoint>,
+    pub created: Vec<Utxo>,
+    /// Spent transaction that created the UTXOs, if tappy spent it
+    pub spend: Option<Spend>,
+}
+
 impl State {
     pub fn new() -> Self {
         Self {
+            version: CURRENT_VERSION,
+            network: bitcoin::Network::Regtest,
             passive_keys: HashMap::new(),
             active_keys: HashMap::new(),
+            key_origins: HashMap::new(),
+            key_labels: HashMap::new(),
+            watch_keys: HashSet::new(),
             passive_images: HashMap::new(),
             active_images: HashMap::new(),
-            inbound_address: None,
+            image_labels: HashMap::new(),
+            watch_images: HashSet::new(),
+            inbound_addresses: HashMap::new(),
             utxos: Vec::new(),
+            utxo_labels: HashMap::new(),
             inputs: HashMap::new(),
             outputs: HashMap::new(),
+            tx_version: 2,
             locktime: LockTime::ZERO,
             fee: 0,
+            feerate: None,
+            last_spend: None,
+            history: Vec::new(),
+            templates: HashMap::new(),
         }
     }
 
+    /// Load state from the given path
+    ///
+    /// Files of older versions are migrated to the current version
+    /// (a missing version counts as version 0)
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        let state = serde_json::from_reader(reader)?;
+        let mut value: Value = serde_json::from_reader(reader)?;
+
+        if let Some(map) = value.as_object_mut() {
+            let version = map.get("version").and_then(Value::as_u64).unwrap_or(0);
+            if version > u64::from(CURRENT_VERSION) {
+                return Err(Error::UnsupportedVersion(version));
+            }
+
+            for migrate in &MIGRATIONS[version as usize..] {
+                migrate(map);
+            }
+            map.insert("version".to_string(), Value::from(CURRENT_VERSION));
+        }
+
+        let state: Self = serde_json::from_value(value)?;
+        state.validate()?;
         Ok(state)
     }
 
+    /// Check that every key pair matches its public key
+    /// and every preimage hashes to its image
+    ///
+    /// Reports all mismatches at once
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut mismatches = Vec::new();
+
+        for (public_key, keypair) in self.passive_keys.iter().chain(&self.active_keys) {
+            if keypair.public_key() != public_key.inner {
+                mismatches.push(format!(
+                    "Key pair does not belong to public key {}",
+                    util::into_xonly(*public_key)
+                ));
+            }
+        }
+        for (image, preimage) in self.passive_images.iter().chain(&self.active_images) {
+            if sha256::Hash::hash(preimage) != *image {
+                mismatches.push(format!("Preimage does not hash to image {}", image));
+            }
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            mismatches.sort();
+            Err(Error::InconsistentState(mismatches))
+        }
+    }
+
+    /// Save state to the given path
+    ///
+    /// The state is first written to a temporary file next to the destination,
+    /// which then replaces the destination in one atomic step
+    ///
+    /// During initialization, fails if the destination already exists
     pub fn save<P: AsRef<Path>>(&self, path: P, init: bool) -> Result<(), Error> {
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create_new(init)
-            .open(path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, self)?;
+        let path = path.as_ref();
+
+        // Checked up front, so a failed write leaves no empty file behind
+        if init && path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            )
+            .into());
+        }
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        if let Err(error) = self.write_to(&tmp_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(error);
+        }
+
+        fs::rename(&tmp_path, path)?;
         Ok(())
     }
 
+    fn write_to(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("network", self.network.to_string()),
+            ("utxos", self.utxos.len().to_string()),
+            ("in", self.inputs.len().to_string()),
+            ("out", self.outputs.len().to_string()),
+            ("fee", self.fee.to_string()),
+            (
+                "locktime",
+                if self.locktime_enabled() { "on" } else { "off" }.to_string(),
+            ),
+        ]
+    }
+
+    pub(crate) fn locktime_description(&self) -> String {
+        util::describe_locktime(self.locktime)
+    }
+
     pub(crate) fn locktime_enabled(&self) -> bool {
         for input in self.inputs.values() {
             if input.sequence.enables_absolute_lock_time() {
@@ -116,30 +425,83 @@ impl State {
 
         false
     }
+
+    /// Return true if any input has a relative locktime (BIP68)
+    pub(crate) fn has_relative_locktime(&self) -> bool {
+        self.inputs
+            .values()
+            .any(|input| input.sequence.is_relative_lock_time())
+    }
+
+    /// Return true if the input spends an outpoint that is not in the UTXO set
+    pub(crate) fn is_external(&self, input: &Input) -> bool {
+        !self
+            .utxos
+            .iter()
+            .any(|utxo| utxo.outpoint == input.utxo.outpoint)
+    }
 }
 
 impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Network: {}", self.network)?;
         writeln!(f, "Keys (xonly: WIF) [disabled for spending]:")?;
-        fmt_keys(&self.passive_keys, f)?;
+        fmt_keys(
+            &self.passive_keys,
+            &self.key_origins,
+            &self.key_labels,
+            self.network,
+            f,
+        )?;
         writeln!(f, "Keys (xonly: WIF) [enabled]:")?;
-        fmt_keys(&self.active_keys, f)?;
+        fmt_keys(
+            &self.active_keys,
+            &self.key_origins,
+            &self.key_labels,
+            self.network,
+            f,
+        )?;
+        writeln!(f, "Keys (xonly) [watch-only]:")?;
+        for public_key in self.watch_keys.iter().sorted() {
+            let xonly = util::into_xonly(*public_key);
+            match self.key_labels.get(public_key) {
+                Some(label) => writeln!(f, "  {} ({})", label, xonly)?,
+                None => writeln!(f, "  {}", xonly)?,
+            }
+        }
         writeln!(f, "Images (preimage: image) [disabled for spending]:")?;
-        fmt_images(&self.passive_images, f)?;
+        fmt_images(&self.passive_images, &self.image_labels, f)?;
         writeln!(f, "Images (preimage: image) [enabled]:")?;
-        fmt_images(&self.active_images, f)?;
+        fmt_images(&self.active_images, &self.image_labels, f)?;
+        writeln!(f, "Images [watch-only]:")?;
+        for image in self.watch_images.iter().sorted() {
+            match self.image_labels.get(image) {
+                Some(label) => writeln!(f, "  {} ({})", label, image)?,
+                None => writeln!(f, "  {}", image)?,
+            }
+        }
+        writeln!(f, "Inbound addresses:")?;
+        for index in self.inbound_addresses.keys().sorted() {
+            writeln!(f, "  {}: {}", index, self.inbound_addresses[index])?;
+        }
         writeln!(f, "Inputs:")?;
         for index in self.inputs.keys().sorted() {
-            writeln!(f, "  {}: {}", index, self.inputs[index])?;
+            let input = &self.inputs[index];
+            if self.is_external(input) {
+                writeln!(f, "  {}: {} (external)", index, input)?;
+            } else {
+                writeln!(f, "  {}: {}", index, input)?;
+            }
         }
         writeln!(f, "Outputs:")?;
         for index in self.outputs.keys().sorted() {
             writeln!(f, "  {}: {}", index, self.outputs[index])?;
         }
+        writeln!(f, "Transaction version: {}", self.tx_version)?;
         writeln!(
             f,
-            "Locktime: ={} blocks [{}]",
-            self.locktime,
+            "Locktime: {} [{}]",
+            self.locktime_description(),
             if self.locktime_enabled() {
                 "enabled"
             } else {
@@ -147,6 +509,41 @@ impl fmt::Display for State {
             }
         )?;
         write!(f, "Fee: {} sat", self.fee)?;
+        if let Some(feerate) = self.feerate {
+            write!(f, " (target {} sat / vB)", feerate)?;
+        }
+        writeln!(f)?;
+
+        let input_funds = util::get_input_funds(self);
+        let output_funds = util::get_output_funds(self);
+        let spent_funds = util::get_spent_funds(self);
+        writeln!(f, "Balance:")?;
+        writeln!(f, "  Inputs: {} sat", input_funds)?;
+        writeln!(f, "  Outputs: {} sat", output_funds)?;
+        match util::get_fee_output(self) {
+            Some(index) => writeln!(f, "  Fee: {} sat (paid by output {})", self.fee, index)?,
+            None => writeln!(f, "  Fee: {} sat", self.fee)?,
+        }
+        match util::get_remaining_funds(self) {
+            Ok(shares) if !shares.is_empty() => {
+                let shares = shares
+                    .iter()
+                    .map(|(index, value)| format!("{} sat to output {}", value, index))
+                    .join(", ");
+                write!(f, "  Remaining: {}", shares)?
+            }
+            Ok(_) if input_funds >= spent_funds => {
+                let excess = input_funds - spent_funds;
+                write!(f, "  Unassigned: {} sat (goes to fee)", excess)?
+            }
+            Err(Error::FeeOutputWithRemaining) => {
+                write!(f, "  Invalid: {}", Error::FeeOutputWithRemaining)?
+            }
+            Ok(_) | Err(_) => {
+                let missing = spent_funds - input_funds;
+                write!(f, "  Missing: {} sat (not enough funds)", missing)?
+            }
+        }
 
         Ok(())
     }
@@ -154,12 +551,23 @@ impl fmt::Display for State {
 
 fn fmt_keys(
     keys: &HashMap<bitcoin::PublicKey, bitcoin::KeyPair>,
+    origins: &HashMap<bitcoin::PublicKey, KeySource>,
+    labels: &HashMap<bitcoin::PublicKey, String>,
+    network: bitcoin::Network,
     f: &mut fmt::Formatter<'_>,
 ) -> fmt::Result {
-    for keypair in keys.values() {
+    for (public_key, keypair) in keys {
         let (xonly, _) = keypair.x_only_public_key();
-        let prv = bitcoin::PrivateKey::new(keypair.secret_key(), bitcoin::Network::Regtest);
-        writeln!(f, "  {}: {}", xonly, prv.to_wif())?;
+        let prv = bitcoin::PrivateKey::new(keypair.secret_key(), network);
+        match labels.get(public_key) {
+            Some(label) => write!(f, "  {} ({}): {}", label, xonly, prv.to_wif())?,
+            None => write!(f, "  {}: {}", xonly, prv.to_wif())?,
+        }
+        if let Some((fingerprint, path)) = origins.get(public_key) {
+            let path = path.to_string();
+            write!(f, " [{}{}]", fingerprint, path.trim_start_matches('m'))?;
+        }
+        writeln!(f)?;
     }
 
     Ok(())
@@ -167,10 +575,14 @@ fn fmt_keys(
 
 fn fmt_images(
     images: &HashMap<sha256::Hash, Preimage32>,
+    labels: &HashMap<sha256::Hash, String>,
     f: &mut fmt::Formatter<'_>,
 ) -> fmt::Result {
     for (image, preimage) in images {
-        write!(f, "  {}: ", image)?;
+        match labels.get(image) {
+            Some(label) => write!(f, "  {} ({}): ", label, image)?,
+            None => write!(f, "  {}: ", image)?,
+        }
         for byte in preimage {
             write!(f, "{:02x}", byte)?;
         }
@@ -179,3 +591,150 @@ fn fmt_images(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enable_new_key, test_dir};
+
+    #[test]
+    fn failed_save_keeps_original_file() {
+        let dir = test_dir("failed-save");
+        let path = dir.join("state.json");
+        State::new().save(&path, true).unwrap();
+        let original = fs::read(&path).unwrap();
+
+        // A directory in place of the temporary file makes writing fail
+        fs::create_dir(dir.join("state.json.tmp")).unwrap();
+        let mut state = State::new();
+        state.fee = 12345;
+        assert!(state.save(&path, false).is_err());
+
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert_eq!(State::load(&path).unwrap().fee, State::new().fee);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_init_leaves_no_file() {
+        let dir = test_dir("failed-init");
+        let path = dir.join("state.json");
+
+        // A directory in place of the temporary file makes writing fail
+        fs::create_dir(dir.join("state.json.tmp")).unwrap();
+        assert!(State::new().save(&path, true).is_err());
+
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn init_fails_if_file_exists() {
+        let dir = test_dir("init-exists");
+        let path = dir.join("state.json");
+        State::new().save(&path, true).unwrap();
+
+        assert!(State::new().save(&path, true).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_file() {
+        let dir = test_dir("save");
+        let path = dir.join("state.json");
+        State::new().save(&path, true).unwrap();
+
+        let mut state = State::new();
+        state.fee = 12345;
+        state.save(&path, false).unwrap();
+
+        assert_eq!(State::load(&path).unwrap().fee, 12345);
+        assert!(!dir.join("state.json.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_reports_all_mismatches() {
+        let mut state = State::new();
+        let first = enable_new_key(&mut state).public_key(bitcoin::secp256k1::Parity::Even);
+        let second = enable_new_key(&mut state).public_key(bitcoin::secp256k1::Parity::Even);
+        let first_key = bitcoin::PublicKey::new(first);
+        let second_key = bitcoin::PublicKey::new(second);
+        // Swap the key pairs, so neither matches its public key
+        let first_pair = state.active_keys[&first_key];
+        let second_pair = state.active_keys[&second_key];
+        state.active_keys.insert(first_key, second_pair);
+        state.active_keys.insert(second_key, first_pair);
+        let image = sha256::Hash::hash(&[1; 32]);
+        state.passive_images.insert(image, [2; 32]);
+
+        let mismatches = match state.validate() {
+            Err(Error::InconsistentState(mismatches)) => mismatches,
+            other => panic!("expected inconsistent state, got {:?}", other),
+        };
+        assert_eq!(mismatches.len(), 3);
+        assert!(mismatches.iter().any(|m| m.contains(&image.to_string())));
+        assert!(mismatches
+            .iter()
+            .any(|m| m.contains(&util::into_xonly(first_key).to_string())));
+    }
+
+    /// First character of the WIF that printing the state shows for the key
+    fn wif_prefix(state: &State, key: bitcoin::XOnlyPublicKey) -> char {
+        let printed = state.to_string();
+        let line = printed
+            .lines()
+            .find(|line| line.trim_start().starts_with(&format!("{}: ", key)))
+            .unwrap();
+        line.rsplit(": ").next().unwrap().chars().next().unwrap()
+    }
+
+    #[test]
+    fn wif_matches_network() {
+        let mut state = State::new();
+        let key = enable_new_key(&mut state);
+
+        state.network = bitcoin::Network::Bitcoin;
+        assert!(matches!(wif_prefix(&state, key), 'K' | 'L'));
+        state.network = bitcoin::Network::Regtest;
+        assert_eq!(wif_prefix(&state, key), 'c');
+    }
+
+    #[test]
+    fn unversioned_file_is_migrated() {
+        let dir = test_dir("migrate");
+        let path = dir.join("state.json");
+        let descriptor = "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)";
+        let utxo = serde_json::json!({
+            "descriptor": descriptor,
+            "outpoint": "3e59661081cbdbfa69e68a9e679a88f3d9070e209aeb11ff424ea06c806a1e7a:0",
+            "output": {
+                "value": 100000,
+                "script_pubkey": "51201ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f"
+            }
+        });
+        let unversioned = serde_json::json!({
+            "passive_keys": {},
+            "active_keys": {},
+            "passive_images": {},
+            "active_images": {},
+            "inbound_address": descriptor,
+            "utxos": [utxo],
+            "inputs": {"0": {"utxo": utxo, "sequence": 4294967295u32}},
+            "outputs": {"0": {"value": 0, "descriptor": descriptor}},
+            "locktime": {"Blocks": 0},
+            "fee": 200
+        });
+        fs::write(&path, unversioned.to_string()).unwrap();
+
+        let state = State::load(&path).unwrap();
+        assert_eq!(state.version, CURRENT_VERSION);
+        assert_eq!(state.inbound_addresses.len(), 1);
+        assert_eq!(state.inputs[&0].sighash, SchnorrSighashType::All);
+        assert_eq!(state.outputs[&0].weight, 1);
+        assert!(!state.outputs[&0].pays_fee);
+        assert!(state.last_spend.is_none());
+        assert_eq!(state.tx_version, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ColorChoice {
+    /// Color if stdout is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
+
+pub fn init(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    let _ = ENABLED.set(enabled);
+}
+
+fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+pub struct Painted<T> {
+    value: T,
+    code: &'static str,
+}
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if enabled() {
+            write!(f, "\x1b[{}m{}\x1b[0m", self.code, self.value)
+        } else {
+            write!(f, "{}", self.value)
+        }
+    }
+}
+
+/// Style for newly created items
+pub fn added<T: fmt::Display>(value: T) -> Painted<T> {
+    Painted { value, code: "32" }
+}
+
+/// Style for deleted or replaced items
+pub fn removed<T: fmt::Display>(value: T) -> Painted<T> {
+    Painted { value, code: "33" }
+}
+
+/// Style for errors
+pub fn error<T: fmt::Display>(value: T) -> Painted<T> {
+    Painted { value, code: "31" }
+}
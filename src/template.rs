@@ -0,0 +1,85 @@
+use crate::descriptor;
+use crate::error::Error;
+use crate::state::State;
+use itertools::Itertools;
+use miniscript::{bitcoin, Descriptor};
+
+/// Find the numbers of all placeholders `$0`, `$1`, ... in the template
+///
+/// Returns the byte range and number of each placeholder
+fn placeholders(template: &str) -> Vec<(std::ops::Range<usize>, usize)> {
+    let mut found = Vec::new();
+    for (start, _) in template.match_indices('$') {
+        let digits = template[start + 1..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if let Ok(number) = template[start + 1..start + 1 + digits].parse() {
+            found.push((start..start + 1 + digits, number));
+        }
+    }
+    found
+}
+
+/// Return the number of arguments that the template expects
+///
+/// Placeholders must be numbered from `$0` upwards without gaps
+fn count_arguments(template: &str) -> Result<usize, Error> {
+    let numbers: Vec<usize> = placeholders(template)
+        .into_iter()
+        .map(|(_, number)| number)
+        .sorted()
+        .dedup()
+        .collect();
+    if numbers.iter().enumerate().any(|(i, number)| i != *number) {
+        return Err(Error::InvalidTemplate);
+    }
+    Ok(numbers.len())
+}
+
+/// Save template under the given name
+///
+/// Returns the replaced template, if any
+pub fn save_template(
+    state: &mut State,
+    name: String,
+    template: String,
+) -> Result<Option<String>, Error> {
+    count_arguments(&template)?;
+    Ok(state.templates.insert(name, template))
+}
+
+pub fn delete_template(state: &mut State, name: &str) -> Result<String, Error> {
+    state.templates.remove(name).ok_or(Error::MissingTemplate)
+}
+
+pub fn list_templates(state: &State) {
+    println!("Templates:");
+    for (name, template) in state.templates.iter().sorted() {
+        println!("{}: {}", name, template);
+    }
+}
+
+/// Substitute the placeholders of the template with the arguments and parse the result
+pub fn use_template(
+    state: &State,
+    name: &str,
+    arguments: &[String],
+) -> Result<Descriptor<bitcoin::XOnlyPublicKey>, Error> {
+    let template = state.templates.get(name).ok_or(Error::MissingTemplate)?;
+    let expected = count_arguments(template)?;
+    if arguments.len() != expected {
+        return Err(Error::TemplateArguments(expected, arguments.len()));
+    }
+
+    let mut descriptor = String::with_capacity(template.len());
+    let mut last = 0;
+    for (range, number) in placeholders(template) {
+        descriptor.push_str(&template[last..range.start]);
+        descriptor.push_str(&arguments[number]);
+        last = range.end;
+    }
+    descriptor.push_str(&template[last..]);
+
+    descriptor::parse(&descriptor)
+}
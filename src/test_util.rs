@@ -0,0 +1,27 @@
+use crate::key;
+use crate::state::State;
+use crate::util;
+use miniscript::{bitcoin, Descriptor};
+use std::fs;
+use std::path::PathBuf;
+
+/// Empty directory for the files of one test
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tappy-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Generate a key pair, enable it for spending and return its public key
+pub fn enable_new_key(state: &mut State) -> bitcoin::XOnlyPublicKey {
+    key::generate_keys(state, 1).unwrap();
+    let (public_key, keypair) = state.passive_keys.drain().next().unwrap();
+    state.active_keys.insert(public_key, keypair);
+    util::into_xonly(public_key)
+}
+
+/// Enable a new key and return its key-path descriptor
+pub fn new_descriptor(state: &mut State) -> Descriptor<bitcoin::XOnlyPublicKey> {
+    Descriptor::new_tr(enable_new_key(state), None).unwrap()
+}
@@ -1,44 +1,511 @@
 use crate::error::Error;
-use crate::state::{Input, State, Utxo};
+use crate::input;
+use crate::output;
+use crate::state::{Destination, HistoryEntry, Input, State, Utxo};
+use crate::style;
 use crate::util;
 use itertools::Itertools;
-use miniscript::bitcoin;
-use miniscript::bitcoin::locktime::Height;
-use miniscript::bitcoin::{LockTime, Sequence};
+use miniscript::bitcoin::hashes::hex::ToHex;
+use miniscript::bitcoin::{LockTime, SchnorrSighashType, Sequence};
+use miniscript::{bitcoin, Descriptor};
+use std::collections::{HashMap, HashSet};
 
-pub fn update_locktime(state: &mut State, height: Height) -> Result<(), Error> {
-    state.locktime = LockTime::Blocks(height);
+/// Set the locktime of the transaction
+///
+/// A nonzero locktime requires an input whose sequence enables it
+pub fn update_locktime(state: &mut State, locktime: LockTime) -> Result<(), Error> {
+    if locktime != LockTime::ZERO && !state.locktime_enabled() {
+        return Err(Error::LocktimeDisabled);
+    }
+    state.locktime = locktime;
+    Ok(())
+}
+
+pub fn update_version(state: &mut State, version: i32) -> Result<(), Error> {
+    state.tx_version = version;
     Ok(())
 }
 
 pub fn update_fee(state: &mut State, value: u64) -> Result<(), Error> {
     state.fee = value;
+    state.feerate = None;
+    Ok(())
+}
+
+pub fn update_feerate(state: &mut State, feerate: f64) -> Result<(), Error> {
+    state.feerate = Some(feerate);
     Ok(())
 }
 
-pub fn finalize_transaction(state: &mut State, txid: bitcoin::Txid) -> Result<(), Error> {
-    for (_, input) in state.inputs.drain() {
-        if let Some(index) = state.utxos.iter().position(|x| x == &input.utxo) {
-            let _utxo = state.utxos.remove(index);
+/// Let the output with the given index pay the fee, or the inputs if no index is given
+pub fn update_fee_output(state: &mut State, output_index: Option<usize>) -> Result<(), Error> {
+    if let Some(index) = output_index {
+        let output = state.outputs.get(&index).ok_or(Error::MissingOutput)?;
+        if output.receives_remaining() {
+            return Err(Error::FeeOutputWithRemaining);
         }
     }
 
-    let mut is_first_input = true;
-    let remaining_funds = util::get_remaining_funds(state)?;
+    for (index, output) in state.outputs.iter_mut() {
+        output.pays_fee = Some(*index) == output_index;
+    }
+    // Fail early if zero-value outputs also receive the remaining funds
+    if output_index.is_some() {
+        util::get_remaining_funds(state)?;
+    }
+
+    Ok(())
+}
+
+/// Set the fee according to the given feerate in sat / vB
+///
+/// Returns the estimated transaction vsize
+pub fn apply_feerate(state: &mut State, feerate: f64) -> Result<usize, Error> {
+    if state.inputs.is_empty() {
+        return Err(Error::MissingInput);
+    }
+
+    let vsize = estimate_transaction_weight(state)?.div_ceil(4);
+    state.fee = (feerate * vsize as f64).ceil() as u64;
+    // Fail early if the remaining funds or the output that pays the fee cannot cover it
+    util::get_remaining_funds(state)?;
+    util::get_fee_output_value(state)?;
+
+    Ok(vsize)
+}
+
+/// Print the estimated size of the current transaction
+/// and the feerate of the current fee
+///
+/// Nothing is signed
+pub fn print_estimate(state: &State) -> Result<(), Error> {
+    if state.inputs.is_empty() {
+        return Err(Error::MissingInput);
+    }
+
+    let weight = estimate_transaction_weight(state)?;
+    let vsize = weight.div_ceil(4);
+    println!("Weight: {} WU", weight);
+    println!("Vsize: {} vB", vsize);
+    println!("Fee: {} sat", state.fee);
+    println!("Feerate: {:.2} sat / vB", state.fee as f64 / vsize as f64);
+
+    if let Some(feerate) = state.feerate {
+        let fee = (feerate * vsize as f64).ceil() as u64;
+        println!("Fee for target {} sat / vB: {} sat", feerate, fee);
+    }
+
+    Ok(())
+}
+
+/// Estimate an upper bound on the weight of the current transaction
+fn estimate_transaction_weight(state: &State) -> Result<usize, Error> {
+    let input_descriptors = state
+        .inputs
+        .keys()
+        .sorted()
+        .map(|index| &state.inputs[index].utxo.descriptor);
+    let output_scripts = state
+        .outputs
+        .keys()
+        .sorted()
+        .map(|index| state.outputs[index].script_pubkey())
+        .collect();
+    util::estimate_weight(input_descriptors, output_scripts)
+}
+
+pub fn estimate_chain_fees(state: &State, depth: u32, feerate: f64) -> Result<(), Error> {
+    if state.inputs.is_empty() {
+        return Err(Error::MissingInput);
+    }
+    let first_output = state.outputs.get(&0).ok_or(Error::MissingOutput)?;
+
+    let vsize = estimate_transaction_weight(state)?.div_ceil(4);
+    let fee = (feerate * vsize as f64).ceil() as u64;
+    println!("Transaction #0: {} vB, {} sat fee", vsize, fee);
+
+    let mut total_fee = fee;
+    // The first transaction pays the estimated fee instead of the fee of the state
+    let mut estimate = state.clone();
+    estimate.fee = fee;
+    let mut value = if first_output.receives_remaining() {
+        util::get_remaining_funds(&estimate)?
+            .into_iter()
+            .find(|(index, _)| *index == 0)
+            .map(|(_, share)| share)
+            .expect("output 0 receives remaining funds")
+    } else {
+        match util::get_fee_output_value(&estimate)? {
+            Some((0, value)) => value,
+            _ => first_output.value,
+        }
+    };
+
+    // Each following transaction spends the first output of its parent
+    // and pays everything except the fee to the same descriptor
+    let descriptor = match &first_output.destination {
+        Destination::Descriptor(descriptor) => descriptor,
+        Destination::Address(_) | Destination::Script(_) | Destination::Data(_) => {
+            return Err(Error::NotDescriptor)
+        }
+    };
+    let vsize = util::estimate_vsize([descriptor], vec![descriptor.script_pubkey()])?;
+    let fee = (feerate * vsize as f64).ceil() as u64;
+
+    for index in 1..depth {
+        value = value.checked_sub(fee).ok_or(Error::NotEnoughFunds)?;
+        total_fee += fee;
+        println!("Transaction #{}: {} vB, {} sat fee", index, vsize, fee);
+    }
+
+    println!("Total fee: {} sat", total_fee);
+    println!("Remaining value: {} sat", value);
+
+    Ok(())
+}
+
+/// Remove all inputs and outputs and reset fee and locktime
+///
+/// Keys, images and UTXOs stay untouched
+pub fn clear_transaction(state: &mut State) {
+    for (index, input) in state.inputs.drain().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
+        println!(
+            "{}",
+            style::removed(format_args!("Deleting input #{}: {}", index, input))
+        );
+    }
+    for (index, output) in state.outputs.drain().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
+        println!(
+            "{}",
+            style::removed(format_args!("Deleting output #{}: {}", index, output))
+        );
+    }
+
+    state.fee = 0;
+    state.locktime = LockTime::ZERO;
+    println!("Fee: 0 sat");
+    println!("Locktime: {}", state.locktime_description());
+}
+
+/// Replace the inputs and outputs by a single input that spends the UTXO
+/// and a single output to the descriptor that receives the remaining funds
+///
+/// With a feerate, the fee is set from the estimated vsize.
+/// Returns the value of the output
+pub fn sweep_utxo(
+    state: &mut State,
+    utxo_index: usize,
+    descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    feerate: Option<f64>,
+) -> Result<u64, Error> {
+    if utxo_index >= state.utxos.len() {
+        return Err(Error::MissingUtxo);
+    }
+    util::verify_taproot(&descriptor)?;
+
+    state.inputs.clear();
+    state.outputs.clear();
+    input::add_from_utxo(state, 0, utxo_index)?;
+    output::add_output(state, 0, descriptor, 0, 1)?;
+
+    if let Some(feerate) = feerate {
+        let vsize = apply_feerate(state, feerate)?;
+        println!("Fee: {} sat (estimated {} vB)", state.fee, vsize);
+    }
+
+    let shares = util::get_remaining_funds(state)?;
+    Ok(shares[0].1)
+}
+
+/// Incremental relay feerate in sat / vB that a replacement must add (BIP125)
+const INCREMENTAL_FEERATE: u64 = 1;
+
+/// Replace the transaction by a draft that spends the same UTXOs as the spent
+/// transaction with the given txid, at a higher feerate
+///
+/// The change output receives the remaining funds, so it pays the fee increase.
+/// Without index, it is the only output to a descriptor.
+/// If the spent transaction was finalized, its finalization is reverted.
+/// Returns the fee of the replacement
+pub fn replace_transaction(
+    state: &mut State,
+    txid: bitcoin::Txid,
+    feerate: f64,
+    change_index: Option<usize>,
+) -> Result<u64, Error> {
+    let (spend, history_position) = match &state.last_spend {
+        Some(spend) if spend.transaction.txid() == txid => (spend.clone(), None),
+        _ => {
+            let position = find_finalized_spend(state, txid)?;
+            let spend = state.history[position].spend.clone();
+            (spend.expect("entry with spend"), Some(position))
+        }
+    };
+    let input_value: u64 = spend.spent.iter().map(|utxo| utxo.output.value).sum();
+    let output_value: u64 = spend
+        .transaction
+        .output
+        .iter()
+        .map(|txout| txout.value)
+        .sum();
+    let old_fee = input_value - output_value;
+
+    if !spend.transaction.is_explicitly_rbf() {
+        println!(
+            "{}",
+            style::error(format_args!(
+                "Warning: {} does not signal RBF; nodes without full RBF reject the replacement",
+                txid
+            ))
+        );
+    }
+
+    let change_index = match change_index {
+        Some(index) if index < spend.outputs.len() => index,
+        Some(_) => return Err(Error::MissingOutput),
+        None => spend
+            .outputs
+            .iter()
+            .positions(|output| matches!(output.destination, Destination::Descriptor(_)))
+            .exactly_one()
+            .map_err(|_| Error::AmbiguousChange)?,
+    };
+
+    if let Some(position) = history_position {
+        revert_finalization(state, position);
+    }
+    state.inputs.clear();
+    state.outputs.clear();
+    state.tx_version = spend.transaction.version;
+    state.locktime = LockTime::from_consensus(spend.transaction.lock_time.0);
+
+    // Spending at least one of the same UTXOs is what makes this a replacement
+    for (input_index, (utxo, txin)) in spend
+        .spent
+        .into_iter()
+        .zip(&spend.transaction.input)
+        .enumerate()
+    {
+        let sequence = if txin.sequence.is_rbf() {
+            txin.sequence
+        } else {
+            Sequence::ENABLE_RBF_NO_LOCKTIME
+        };
+        let input = Input {
+            utxo,
+            sequence,
+            sighash: SchnorrSighashType::All,
+        };
+        println!(
+            "{}",
+            style::added(format_args!("New txin #{}: {}", input_index, input))
+        );
+        state.inputs.insert(input_index, input);
+    }
+
+    for (output_index, mut output) in spend.outputs.into_iter().enumerate() {
+        output.pays_fee = false;
+        if output_index == change_index {
+            output.value = 0;
+            output.weight = 1;
+        }
+        println!(
+            "{}",
+            style::added(format_args!("New txout #{}: {}", output_index, output))
+        );
+        state.outputs.insert(output_index, output);
+    }
+
+    state.feerate = None;
+    let vsize = apply_feerate(state, feerate)?;
+    let min_fee = old_fee + INCREMENTAL_FEERATE * vsize as u64;
+    if state.fee < min_fee {
+        println!(
+            "Raising fee to {} sat: the old fee of {} sat plus {} sat / vB (BIP125)",
+            min_fee, old_fee, INCREMENTAL_FEERATE
+        );
+        state.fee = min_fee;
+        util::get_remaining_funds(state)?;
+    }
+
+    Ok(state.fee)
+}
+
+/// Return the position in the history of the finalized transaction with the given txid
+///
+/// Fails if tappy did not spend it or if a later transaction spends its outputs
+fn find_finalized_spend(state: &State, txid: bitcoin::Txid) -> Result<usize, Error> {
+    let position = state
+        .history
+        .iter()
+        .rposition(|entry| entry.txid == txid && entry.spend.is_some())
+        .ok_or(Error::UnknownSpend(txid))?;
+    let is_spent_later = state.history[position + 1..]
+        .iter()
+        .flat_map(|entry| &entry.spent)
+        .any(|outpoint| outpoint.txid == txid);
+    if is_spent_later {
+        return Err(Error::SpentReplacement(txid));
+    }
+
+    Ok(position)
+}
+
+/// Remove the history entry at the given position and its UTXOs,
+/// and restore the UTXOs that its transaction spent
+fn revert_finalization(state: &mut State, position: usize) {
+    let entry = state.history.remove(position);
+    let txid = entry.txid;
+    let spent = entry.spend.map(|spend| spend.spent).unwrap_or_default();
+
+    // Labels carry back to the spent UTXOs with the same descriptor
+    let mut labels = HashMap::new();
 
-    for (output_index, mut output) in state.outputs.drain().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
-        if let Some((index, value)) = remaining_funds {
-            if output_index == index {
-                output.value = value;
+    state.utxos.retain(|utxo| {
+        let is_created = utxo.outpoint.txid == txid;
+        if is_created {
+            println!(
+                "{}",
+                style::removed(format_args!("Removing UTXO (replaced): {}", utxo))
+            );
+            if let Some(label) = state.utxo_labels.remove(&utxo.outpoint) {
+                labels
+                    .entry(utxo.output.script_pubkey.clone())
+                    .or_insert(label);
             }
         }
+        !is_created
+    });
 
+    for utxo in spent {
+        if let Some(label) = labels.get(&utxo.output.script_pubkey) {
+            state.utxo_labels.insert(utxo.outpoint, label.clone());
+        }
+        if !state
+            .utxos
+            .iter()
+            .any(|other| other.outpoint == utxo.outpoint)
+        {
+            println!("{}", style::added(format_args!("Restoring UTXO: {}", utxo)));
+            state.utxos.push(utxo);
+        }
+    }
+}
+
+/// Print the contents of a raw transaction
+pub fn print_transaction(tx_hex: &str) -> Result<(), Error> {
+    let tx = util::parse_transaction(tx_hex)?;
+
+    println!("Txid: {}", tx.txid());
+    println!("Wtxid: {}", tx.wtxid());
+    println!("Version: {}", tx.version);
+    println!("Locktime: {}", tx.lock_time);
+    println!("Size: {} B", tx.size());
+    println!("Weight: {} WU", tx.weight());
+    println!("Vsize: {} vB", tx.vsize());
+
+    println!("Inputs:");
+    for (index, input) in tx.input.iter().enumerate() {
+        println!(
+            "  #{}: {} sequence {:#010x}",
+            index,
+            input.previous_output,
+            input.sequence.to_consensus_u32()
+        );
+        let item_sizes = input.witness.iter().map(|item| item.len()).join(", ");
+        println!("    witness item sizes: [{}]", item_sizes);
+    }
+
+    println!("Outputs:");
+    for (index, output) in tx.output.iter().enumerate() {
+        println!("  #{}: {} sat", index, output.value);
+        println!("    script pubkey: {:x}", output.script_pubkey);
+        println!("    script asm: {}", output.script_pubkey.asm());
+    }
+
+    Ok(())
+}
+
+/// Print the witness items of each input in hex, with their sizes
+///
+/// Script-path witnesses end with the leaf script and the control block;
+/// the items before are the satisfaction (signatures and preimages)
+pub fn print_witnesses(tx_hex: &str) -> Result<(), Error> {
+    let tx = util::parse_transaction(tx_hex)?;
+
+    for (index, input) in tx.input.iter().enumerate() {
+        let items: Vec<&[u8]> = input.witness.iter().collect();
+        println!("Input #{} witness ({} items):", index, items.len());
+
+        for (position, item) in items.iter().enumerate() {
+            let role = match (items.len(), items.len() - position) {
+                (1, _) => "key-path signature",
+                (_, 1) => "control block",
+                (_, 2) => "leaf script",
+                _ => "satisfaction",
+            };
+            println!("  {} ({} B): {}", role, item.len(), item.to_hex());
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Convert the outputs of the last spent transaction into UTXOs
+///
+/// The given txid must match the txid of the last spent transaction.
+/// Changes to the transaction of the state after spending are discarded.
+pub fn finalize_transaction(state: &mut State, txid: Option<bitcoin::Txid>) -> Result<(), Error> {
+    let last_spend = state.last_spend.take().ok_or(Error::MissingSpend)?;
+    let last_txid = last_spend.transaction.txid();
+    if let Some(txid) = txid {
+        if txid != last_txid {
+            state.last_spend = Some(last_spend);
+            return Err(Error::TxidMismatch(txid, last_txid));
+        }
+    }
+    let txid = last_txid;
+    let spend = last_spend.clone();
+
+    let mut spent = Vec::new();
+    let mut created = Vec::new();
+    state.inputs.clear();
+    state.outputs.clear();
+
+    // Labels carry over to new UTXOs with the same descriptor
+    let mut labels = HashMap::new();
+
+    for utxo in last_spend.spent {
+        spent.push(utxo.outpoint);
+
+        if let Some(label) = state.utxo_labels.remove(&utxo.outpoint) {
+            labels.entry(utxo.output.script_pubkey).or_insert(label);
+        }
+    }
+
+    // Outpoints identify UTXOs, so whole descriptors need not be compared
+    let spent_outpoints: HashSet<&bitcoin::OutPoint> = spent.iter().collect();
+    state
+        .utxos
+        .retain(|utxo| !spent_outpoints.contains(&utxo.outpoint));
+    let mut known_outpoints: HashSet<bitcoin::OutPoint> =
+        state.utxos.iter().map(|utxo| utxo.outpoint).collect();
+
+    let mut is_first_input = true;
+
+    for (output_index, output) in last_spend.outputs.into_iter().enumerate() {
+        // Outputs without descriptor cannot be spent by tappy
+        let descriptor = match output.destination {
+            Destination::Descriptor(descriptor) => descriptor,
+            Destination::Address(_) | Destination::Script(_) | Destination::Data(_) => continue,
+        };
         let utxo = Utxo {
             output: bitcoin::TxOut {
                 value: output.value,
-                script_pubkey: output.descriptor.script_pubkey(),
+                script_pubkey: descriptor.script_pubkey(),
             },
-            descriptor: output.descriptor,
+            descriptor,
             outpoint: bitcoin::OutPoint {
                 txid,
                 vout: output_index as u32,
@@ -49,17 +516,150 @@ pub fn finalize_transaction(state: &mut State, txid: bitcoin::Txid) -> Result<()
             let first_input = Input {
                 utxo: utxo.clone(),
                 sequence: Sequence::MAX,
+                sighash: SchnorrSighashType::All,
             };
-            println!("New txin: {}", first_input);
+            println!(
+                "{}",
+                style::added(format_args!("New txin: {}", first_input))
+            );
             state.inputs.insert(0, first_input);
             is_first_input = false;
         }
 
-        if !state.utxos.contains(&utxo) {
-            println!("New UTXO: {}", utxo);
+        if let Some(label) = labels.get(&utxo.output.script_pubkey) {
+            state.utxo_labels.insert(utxo.outpoint, label.clone());
+        }
+
+        created.push(utxo.clone());
+
+        if known_outpoints.insert(utxo.outpoint) {
+            println!("{}", style::added(format_args!("New UTXO: {}", utxo)));
             state.utxos.push(utxo);
         }
     }
 
+    state.history.push(HistoryEntry {
+        txid,
+        spent,
+        created,
+        spend: Some(spend),
+    });
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spend;
+    use crate::test_util::new_descriptor;
+
+    /// Spend 100000 sat: 30000 sat to a payment output and the rest to change,
+    /// with a fee of 200 sat
+    fn spent_state() -> (State, bitcoin::Txid) {
+        let mut state = State::new();
+        let descriptor = new_descriptor(&mut state);
+        let outpoint = bitcoin::OutPoint::default();
+        input::add_from_outpoint(&mut state, 0, outpoint, 100_000, descriptor.clone()).unwrap();
+        let payee: bitcoin::Address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"
+            .parse()
+            .unwrap();
+        output::add_address_output(&mut state, 0, payee, 30_000, 1).unwrap();
+        output::add_output(&mut state, 1, descriptor, 0, 1).unwrap();
+        update_fee(&mut state, 200).unwrap();
+
+        spend::get_raw_transaction(&mut state, false, false).unwrap();
+        let txid = state.last_spend.as_ref().unwrap().transaction.txid();
+        (state, txid)
+    }
+
+    #[test]
+    fn replacement_pays_more_from_change() {
+        let (mut state, txid) = spent_state();
+        let fee = replace_transaction(&mut state, txid, 5.0, None).unwrap();
+
+        assert!(fee >= 200 + 5 * 100);
+        assert!(state.inputs[&0].sequence.is_rbf());
+        assert_eq!(state.inputs[&0].utxo.outpoint, bitcoin::OutPoint::default());
+        assert_eq!(state.outputs[&0].value, 30_000);
+        assert!(state.outputs[&1].receives_remaining());
+    }
+
+    #[test]
+    fn replacement_pays_at_least_incremental_relay_fee() {
+        let (mut state, txid) = spent_state();
+        let fee = replace_transaction(&mut state, txid, 0.0, None).unwrap();
+
+        let vsize = estimate_transaction_weight(&state).unwrap().div_ceil(4) as u64;
+        assert_eq!(fee, 200 + INCREMENTAL_FEERATE * vsize);
+    }
+
+    #[test]
+    fn replacing_finalized_transaction_reverts_finalization() {
+        let (mut state, txid) = spent_state();
+        finalize_transaction(&mut state, Some(txid)).unwrap();
+        assert!(state.utxos.iter().any(|utxo| utxo.outpoint.txid == txid));
+
+        replace_transaction(&mut state, txid, 5.0, None).unwrap();
+        assert!(state.history.iter().all(|entry| entry.txid != txid));
+        assert!(state.utxos.iter().all(|utxo| utxo.outpoint.txid != txid));
+        assert!(state
+            .utxos
+            .iter()
+            .any(|utxo| utxo.outpoint == bitcoin::OutPoint::default()));
+    }
+
+    #[test]
+    fn unknown_transaction_cannot_be_replaced() {
+        let (mut state, _) = spent_state();
+        let txid = bitcoin::OutPoint::default().txid;
+
+        assert!(matches!(
+            replace_transaction(&mut state, txid, 5.0, None),
+            Err(Error::UnknownSpend(_))
+        ));
+    }
+
+    #[test]
+    #[ignore = "timing test, run with --ignored"]
+    fn finalize_fifty_inputs() {
+        let mut state = State::new();
+        let descriptor = new_descriptor(&mut state);
+
+        // Clones share the cached spend info instead of recomputing it
+        if let Descriptor::Tr(tr) = &descriptor {
+            let spend_info = tr.spend_info();
+            let cloned = match descriptor.clone() {
+                Descriptor::Tr(cloned) => cloned,
+                _ => unreachable!(),
+            };
+            assert!(std::sync::Arc::ptr_eq(&spend_info, &cloned.spend_info()));
+        }
+
+        for vout in 0..50 {
+            let outpoint = bitcoin::OutPoint {
+                txid: bitcoin::OutPoint::default().txid,
+                vout,
+            };
+            input::add_from_outpoint(
+                &mut state,
+                vout as usize,
+                outpoint,
+                10_000,
+                descriptor.clone(),
+            )
+            .unwrap();
+        }
+        output::add_output(&mut state, 0, descriptor, 0, 1).unwrap();
+        update_fee(&mut state, 5_000).unwrap();
+        spend::get_raw_transaction(&mut state, false, false).unwrap();
+
+        let start = std::time::Instant::now();
+        finalize_transaction(&mut state, None).unwrap();
+        let elapsed = start.elapsed();
+        println!("Finalized 50 inputs in {:?}", elapsed);
+
+        assert_eq!(state.history.last().unwrap().spent.len(), 50);
+        assert!(elapsed < std::time::Duration::from_secs(1));
+    }
+}
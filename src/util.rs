@@ -1,7 +1,129 @@
+use crate::descriptor;
 use crate::error::Error;
-use crate::state::State;
+use crate::state::{Output, State};
+use itertools::Itertools;
+use miniscript::bitcoin::consensus::encode;
+use miniscript::bitcoin::hashes::hex::FromHex;
 use miniscript::descriptor::DescriptorType;
 use miniscript::{bitcoin, Descriptor};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::Path;
+
+/// Describe a relative timelock with its approximate duration
+///
+/// Returns `None` if the sequence has no relative timelock
+pub fn describe_sequence(sequence: bitcoin::Sequence) -> Option<String> {
+    let value = u64::from(sequence.0 & 0xffff);
+
+    if sequence.is_height_locked() {
+        Some(format!(
+            "+{} blocks ({} at 10 min/block)",
+            value,
+            approximate_duration(value * 600)
+        ))
+    } else if sequence.is_time_locked() {
+        let seconds = value * 512;
+        Some(format!(
+            "+{} seconds ({})",
+            seconds,
+            approximate_duration(seconds)
+        ))
+    } else {
+        None
+    }
+}
+
+/// Return true if the sequence is final, signals RBF,
+/// or encodes a relative timelock without any other bits set
+pub fn is_clean_sequence(sequence: bitcoin::Sequence) -> bool {
+    // Type flag (bit 22) and lock value (bits 0 to 15)
+    const LOCK_BITS: u32 = 0x0040_ffff;
+
+    sequence == bitcoin::Sequence::MAX
+        || sequence == bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME
+        || (sequence.is_relative_lock_time() && sequence.0 & !LOCK_BITS == 0)
+}
+
+/// Describe an absolute locktime, with the date for unix times
+pub fn describe_locktime(locktime: bitcoin::LockTime) -> String {
+    match locktime {
+        bitcoin::LockTime::Blocks(height) => format!("={} blocks", height),
+        bitcoin::LockTime::Seconds(time) => format!(
+            "={} unix time ({})",
+            time,
+            format_utc(u64::from(time.to_consensus_u32()))
+        ),
+    }
+}
+
+/// Render a duration roughly in the largest fitting unit
+fn approximate_duration(seconds: u64) -> String {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+
+    if seconds < HOUR {
+        format!("≈{} minutes", (seconds + 30) / 60)
+    } else if seconds < 2 * DAY {
+        format!("≈{:.1} hours", seconds as f64 / HOUR as f64)
+    } else {
+        format!("≈{:.1} days", seconds as f64 / DAY as f64)
+    }
+}
+
+/// Format a unix timestamp as UTC date and time
+pub fn format_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Convert days since 1970-01-01 into a civil date (proleptic Gregorian calendar)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Number of generated keys or images from which the work is spread across threads
+pub const PARALLEL_THRESHOLD: u32 = 256;
+
+/// Default feerate in sat / vB below which a transaction might never confirm
+pub const MIN_PLAUSIBLE_FEERATE: f64 = 0.1;
+
+/// Default feerate in sat / vB above which a transaction likely wastes funds
+pub const MAX_PLAUSIBLE_FEERATE: f64 = 100.0;
+
+/// Return a warning if the feerate is outside the given (minimum, maximum) bounds
+pub fn feerate_warning(fee: u64, vsize: usize, bounds: (f64, f64)) -> Option<String> {
+    let (min_feerate, max_feerate) = bounds;
+    let feerate = fee as f64 / vsize as f64;
+    let problem = if feerate < min_feerate {
+        format!("below {} sat / vB", min_feerate)
+    } else if max_feerate < feerate {
+        format!("above {} sat / vB", max_feerate)
+    } else {
+        return None;
+    };
+
+    Some(format!(
+        "Warning: Feerate of {:.2} sat / vB is {} (fee {} sat, vsize {} vB)",
+        feerate, problem, fee, vsize
+    ))
+}
 
 pub fn verify_taproot(descriptor: &Descriptor<bitcoin::XOnlyPublicKey>) -> Result<(), Error> {
     if let DescriptorType::Tr = descriptor.desc_type() {
@@ -11,24 +133,277 @@ pub fn verify_taproot(descriptor: &Descriptor<bitcoin::XOnlyPublicKey>) -> Resul
     }
 }
 
+/// Read a descriptor from a file, ignoring surrounding whitespace
+pub fn read_descriptor<P: AsRef<Path>>(
+    path: P,
+) -> Result<Descriptor<bitcoin::XOnlyPublicKey>, Error> {
+    let path = path.as_ref();
+    let file_error = |error: &dyn std::fmt::Display| {
+        Error::DescriptorFile(path.to_path_buf(), error.to_string())
+    };
+    let text = std::fs::read_to_string(path).map_err(|error| file_error(&error))?;
+    descriptor::parse(text.trim()).map_err(|error| file_error(&error))
+}
+
+/// Find the single item that has the given label
+pub fn find_by_label<K: Copy + Eq + Hash>(
+    labels: &HashMap<K, String>,
+    label: &str,
+) -> Result<K, Error> {
+    let matches: Vec<K> = labels
+        .iter()
+        .filter(|(_, item_label)| *item_label == label)
+        .map(|(item, _)| *item)
+        .collect();
+
+    match matches.as_slice() {
+        [item] => Ok(*item),
+        [] => Err(Error::UnknownLabel(label.to_string())),
+        _ => Err(Error::AmbiguousLabel(label.to_string(), matches.len())),
+    }
+}
+
 pub fn into_xonly(key: bitcoin::PublicKey) -> bitcoin::XOnlyPublicKey {
     let (xonly, _parity) = key.inner.x_only_public_key();
     xonly
 }
 
-pub fn get_remaining_funds(state: &State) -> Result<Option<(usize, u64)>, Error> {
-    let input_funds = state
+/// Total value of the transaction inputs
+pub fn get_input_funds(state: &State) -> u64 {
+    state
         .inputs
         .values()
-        .fold(0, |x, i| x + i.utxo.output.value);
-    let output_funds = state.outputs.values().fold(0, |x, o| x + o.value) + state.fee;
+        .fold(0, |x, i| x + i.utxo.output.value)
+}
 
-    if let Some((output_index, _)) = state.outputs.iter().find(|(_, o)| o.value == 0) {
-        let remaining_funds = input_funds
-            .checked_sub(output_funds)
-            .ok_or(Error::NotEnoughFunds)?;
-        return Ok(Some((*output_index, remaining_funds)));
+/// Total value of the transaction outputs (without remaining funds)
+pub fn get_output_funds(state: &State) -> u64 {
+    state.outputs.values().fold(0, |x, o| x + o.value)
+}
+
+/// Index of the output that pays the fee, if there is one
+pub fn get_fee_output(state: &State) -> Option<usize> {
+    state
+        .outputs
+        .iter()
+        .find(|(_, output)| output.pays_fee)
+        .map(|(index, _)| *index)
+}
+
+/// Total value that the inputs must cover:
+/// the outputs plus the fee, unless an output pays the fee
+pub fn get_spent_funds(state: &State) -> u64 {
+    match get_fee_output(state) {
+        Some(_) => get_output_funds(state),
+        None => get_output_funds(state) + state.fee,
     }
+}
 
-    Ok(None)
+/// Minimum value of the output that nodes relay, which depends on its script pubkey
+///
+/// For instance, 330 sat for taproot and 546 sat for P2PKH
+pub fn dust_limit(output: &Output) -> u64 {
+    output.script_pubkey().dust_value().to_sat()
+}
+
+/// Value of the output that pays the fee, after subtracting the fee
+///
+/// Returns the output index with its value, or `None` if no output pays the fee
+pub fn get_fee_output_value(state: &State) -> Result<Option<(usize, u64)>, Error> {
+    let index = match get_fee_output(state) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let output = &state.outputs[&index];
+    let dust_limit = dust_limit(output);
+    let value = output
+        .value
+        .checked_sub(state.fee)
+        .filter(|value| *value >= dust_limit)
+        .ok_or(Error::FeeOutputDust(index, dust_limit))?;
+
+    Ok(Some((index, value)))
+}
+
+/// Split the remaining funds (inputs minus outputs minus fee)
+/// among the outputs that receive them, in proportion to their weights
+///
+/// The rounding remainder goes to the lowest output index.
+/// Returns the output indices with their share, in ascending order.
+/// Fails if an output also pays the fee, since the fee would be paid twice
+pub fn get_remaining_funds(state: &State) -> Result<Vec<(usize, u64)>, Error> {
+    let input_funds = get_input_funds(state);
+    let output_funds = get_spent_funds(state);
+
+    let receivers: Vec<(usize, u32)> = state
+        .outputs
+        .iter()
+        .filter(|(_, output)| output.receives_remaining())
+        .map(|(index, output)| (*index, output.weight))
+        .sorted()
+        .collect();
+    if receivers.is_empty() {
+        return Ok(Vec::new());
+    }
+    if get_fee_output(state).is_some() {
+        return Err(Error::FeeOutputWithRemaining);
+    }
+
+    let remaining_funds = input_funds
+        .checked_sub(output_funds)
+        .ok_or(Error::NotEnoughFunds)?;
+    let total_weight: u128 = receivers
+        .iter()
+        .map(|(_, weight)| u128::from(*weight))
+        .sum();
+    let mut shares: Vec<(usize, u64)> = receivers
+        .iter()
+        .map(|(index, weight)| {
+            let share = u128::from(remaining_funds) * u128::from(*weight) / total_weight;
+            (*index, share as u64)
+        })
+        .collect();
+    let assigned: u64 = shares.iter().map(|(_, share)| share).sum();
+    shares[0].1 += remaining_funds - assigned;
+
+    Ok(shares)
+}
+
+/// Return an error if there is no output for the remaining funds
+/// and the inputs pay more than the outputs plus the fee
+pub fn check_unassigned_funds(state: &State) -> Result<(), Error> {
+    if !get_remaining_funds(state)?.is_empty() {
+        return Ok(());
+    }
+
+    let input_funds = get_input_funds(state);
+    let output_funds = get_spent_funds(state);
+
+    match input_funds.checked_sub(output_funds) {
+        Some(excess) if excess > 0 => Err(Error::UnassignedFunds(excess)),
+        _ => Ok(()),
+    }
+}
+
+/// Estimate an upper bound on the virtual size of a transaction
+/// that spends the given descriptors and pays to the given scripts
+pub fn estimate_vsize<'a, I>(
+    input_descriptors: I,
+    output_scripts: Vec<bitcoin::Script>,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = &'a Descriptor<bitcoin::XOnlyPublicKey>>,
+{
+    Ok(estimate_weight(input_descriptors, output_scripts)?.div_ceil(4))
+}
+
+/// Estimate an upper bound on the weight of a transaction
+/// that spends the given descriptors and pays to the given scripts
+pub fn estimate_weight<'a, I>(
+    input_descriptors: I,
+    output_scripts: Vec<bitcoin::Script>,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = &'a Descriptor<bitcoin::XOnlyPublicKey>>,
+{
+    let mut tx = bitcoin::Transaction {
+        version: 2,
+        lock_time: bitcoin::PackedLockTime::ZERO,
+        input: Vec::new(),
+        output: output_scripts
+            .into_iter()
+            .map(|script_pubkey| bitcoin::TxOut {
+                value: 0,
+                script_pubkey,
+            })
+            .collect(),
+    };
+    // Segwit marker and flag
+    let mut witness_weight = 2;
+
+    for descriptor in input_descriptors {
+        tx.input.push(bitcoin::TxIn::default());
+        // The scriptSig length byte is already part of the transaction weight
+        witness_weight += descriptor.max_satisfaction_weight()? - 4;
+    }
+
+    Ok(tx.weight() + witness_weight)
+}
+
+pub fn parse_transaction(tx_hex: &str) -> Result<bitcoin::Transaction, Error> {
+    let tx_bytes = Vec::<u8>::from_hex(tx_hex)?;
+    let tx = encode::deserialize(&tx_bytes)?;
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input, output};
+
+    /// State that spends the given input value to a fixed output
+    /// and the given zero-value outputs with their weights
+    fn state_with_receivers(input_value: u64, fee: u64, receivers: &[(usize, u32)]) -> State {
+        let descriptor: Descriptor<bitcoin::XOnlyPublicKey> =
+            "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)"
+                .parse()
+                .unwrap();
+        let mut state = State::new();
+        state.fee = fee;
+        let outpoint = bitcoin::OutPoint::default();
+        input::add_from_outpoint(&mut state, 0, outpoint, input_value, descriptor.clone()).unwrap();
+        output::add_output(&mut state, 0, descriptor.clone(), 1_000, 1).unwrap();
+        for (index, weight) in receivers {
+            output::add_output(&mut state, *index, descriptor.clone(), 0, *weight).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn non_taproot_descriptor_is_rejected() {
+        let descriptor: Descriptor<bitcoin::XOnlyPublicKey> =
+            "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)"
+                .parse()
+                .unwrap();
+        assert!(verify_taproot(&descriptor).is_ok());
+
+        let descriptor: Descriptor<bitcoin::XOnlyPublicKey> =
+            "wpkh(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)"
+                .parse()
+                .unwrap();
+        assert!(matches!(
+            verify_taproot(&descriptor),
+            Err(Error::OnlyTaproot)
+        ));
+    }
+
+    #[test]
+    fn remainder_goes_to_lowest_index() {
+        // 10001 sat remain: 5000 each, plus 1 for the lowest index
+        let state = state_with_receivers(12_001, 1_000, &[(2, 1), (1, 1)]);
+        assert_eq!(
+            get_remaining_funds(&state).unwrap(),
+            vec![(1, 5_001), (2, 5_000)]
+        );
+    }
+
+    #[test]
+    fn remaining_funds_split_by_weight() {
+        // 10000 sat remain: 3333 for weight 1 plus the remainder, 6666 for weight 2
+        let state = state_with_receivers(12_000, 1_000, &[(1, 1), (2, 2)]);
+        assert_eq!(
+            get_remaining_funds(&state).unwrap(),
+            vec![(1, 3_334), (2, 6_666)]
+        );
+    }
+
+    #[test]
+    fn remaining_funds_do_not_depend_on_insertion_order() {
+        let first = state_with_receivers(12_001, 1_000, &[(1, 1), (2, 1)]);
+        let second = state_with_receivers(12_001, 1_000, &[(2, 1), (1, 1)]);
+        assert_eq!(
+            get_remaining_funds(&first).unwrap(),
+            get_remaining_funds(&second).unwrap()
+        );
+    }
 }
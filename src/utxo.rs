@@ -1,10 +1,20 @@
 use crate::error::Error;
-use crate::state::{State, Utxo};
+use crate::state::{HistoryEntry, State, Utxo};
+use crate::style;
+use crate::util;
+use itertools::Itertools;
+use miniscript::{bitcoin, Descriptor};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
 
 pub fn list_utxos(state: &State) {
     println!("UTXOs:");
     for (index, utxo) in state.utxos.iter().enumerate() {
-        println!("{}: {}", index, utxo);
+        match state.utxo_labels.get(&utxo.outpoint) {
+            Some(label) => println!("{} ({}): {}", index, label, utxo),
+            None => println!("{}: {}", index, utxo),
+        }
     }
 }
 
@@ -14,5 +24,319 @@ pub fn delete_utxo(state: &mut State, utxo_index: usize) -> Result<Utxo, Error>
     }
 
     let old = state.utxos.remove(utxo_index);
+    state.utxo_labels.remove(&old.outpoint);
     Ok(old)
 }
+
+/// Set label of UTXO, or remove it if no label is given
+pub fn label_utxo(
+    state: &mut State,
+    utxo_index: usize,
+    label: Option<String>,
+) -> Result<(), Error> {
+    let outpoint = state
+        .utxos
+        .get(utxo_index)
+        .ok_or(Error::MissingUtxo)?
+        .outpoint;
+
+    match label {
+        Some(label) => state.utxo_labels.insert(outpoint, label),
+        None => state.utxo_labels.remove(&outpoint),
+    };
+
+    Ok(())
+}
+
+/// UTXO as listed in a JSON file for import
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UtxoEntry {
+    descriptor: Descriptor<bitcoin::XOnlyPublicKey>,
+    txid: bitcoin::Txid,
+    vout: u32,
+    value: u64,
+}
+
+/// Import UTXOs from a JSON file with an array of `{descriptor, txid, vout, value}` objects
+///
+/// UTXOs whose outpoint is already known are skipped.
+/// Invalid entries are reported and skipped, unless `strict` is set.
+pub fn import_utxos<P: AsRef<Path>>(state: &mut State, path: P, strict: bool) -> Result<(), Error> {
+    let text = std::fs::read_to_string(path)?;
+    let values: Vec<serde_json::Value> = serde_json::from_str(&text)?;
+    let (mut added, mut duplicates, mut invalid) = (0, 0, 0);
+
+    for (index, value) in values.into_iter().enumerate() {
+        let entry = serde_json::from_value::<UtxoEntry>(value)
+            .map_err(Error::from)
+            .and_then(|entry| util::verify_taproot(&entry.descriptor).map(|_| entry));
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) if strict => return Err(Error::UtxoEntry(index, error.to_string())),
+            Err(error) => {
+                println!(
+                    "{}",
+                    style::error(format_args!("Skipping entry {}: {}", index, error))
+                );
+                invalid += 1;
+                continue;
+            }
+        };
+
+        let outpoint = bitcoin::OutPoint {
+            txid: entry.txid,
+            vout: entry.vout,
+        };
+        if state.utxos.iter().any(|utxo| utxo.outpoint == outpoint) {
+            duplicates += 1;
+            continue;
+        }
+
+        let utxo = Utxo {
+            output: bitcoin::TxOut {
+                value: entry.value,
+                script_pubkey: entry.descriptor.script_pubkey(),
+            },
+            descriptor: entry.descriptor,
+            outpoint,
+        };
+        println!(
+            "{}",
+            style::added(format_args!("New UTXO #{}: {}", state.utxos.len(), utxo))
+        );
+        state.history.push(HistoryEntry {
+            txid: outpoint.txid,
+            spent: Vec::new(),
+            created: vec![utxo.clone()],
+            spend: None,
+        });
+        state.utxos.push(utxo);
+        added += 1;
+    }
+
+    println!(
+        "Added {} UTXOs, skipped {} duplicates and {} invalid entries",
+        added, duplicates, invalid
+    );
+    Ok(())
+}
+
+pub fn import_transaction(
+    state: &mut State,
+    tx_hex: &str,
+    mut descriptors: Vec<Descriptor<bitcoin::XOnlyPublicKey>>,
+) -> Result<(), Error> {
+    let tx = util::parse_transaction(tx_hex)?;
+    let txid = tx.txid();
+
+    for descriptor in &descriptors {
+        util::verify_taproot(descriptor)?;
+    }
+    // Outputs that pay to an inbound address are imported as well
+    let inbound_start = descriptors.len();
+    let inbound_indices: Vec<usize> = state.inbound_addresses.keys().copied().sorted().collect();
+    descriptors.extend(
+        inbound_indices
+            .iter()
+            .map(|index| state.inbound_addresses[index].clone()),
+    );
+
+    let spent: Vec<_> = tx.input.iter().map(|txin| txin.previous_output).collect();
+    let mut created = Vec::new();
+
+    for (vout, txout) in tx.output.iter().enumerate() {
+        let descriptor = descriptors
+            .iter()
+            .find(|d| d.script_pubkey() == txout.script_pubkey);
+        if let Some(descriptor) = descriptor {
+            let utxo = Utxo {
+                descriptor: descriptor.clone(),
+                outpoint: bitcoin::OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                output: txout.clone(),
+            };
+            created.push(utxo);
+        }
+    }
+
+    if created.is_empty() {
+        return Err(Error::NoMatchingOutput);
+    }
+    for (index, descriptor) in inbound_indices.iter().zip(&descriptors[inbound_start..]) {
+        let inbound_script = descriptor.script_pubkey();
+        if created
+            .iter()
+            .any(|utxo| utxo.output.script_pubkey == inbound_script)
+        {
+            state.inbound_addresses.remove(index);
+        }
+    }
+
+    state.utxos.retain(|utxo| {
+        let is_spent = spent.contains(&utxo.outpoint);
+        if is_spent {
+            println!(
+                "{}",
+                style::removed(format_args!("Removing spent UTXO: {}", utxo))
+            );
+        }
+        !is_spent
+    });
+
+    for utxo in &created {
+        if !state.utxos.contains(utxo) {
+            println!(
+                "{}",
+                style::added(format_args!("New UTXO #{}: {}", state.utxos.len(), utxo))
+            );
+            state.utxos.push(utxo.clone());
+        }
+    }
+
+    state.history.push(HistoryEntry {
+        txid,
+        spent,
+        created,
+        spend: None,
+    });
+
+    Ok(())
+}
+
+/// Replay the history to restore missing UTXOs and remove spent ones
+///
+/// UTXOs that the history never created, such as those of older state files,
+/// are kept and reported, unless `force` is set
+pub fn rebuild_utxos(state: &mut State, force: bool) -> Result<(), Error> {
+    if state.history.is_empty() {
+        return Err(Error::MissingHistory);
+    }
+
+    let mut rebuilt: Vec<Utxo> = Vec::new();
+    let mut created = HashSet::new();
+
+    for entry in &state.history {
+        rebuilt.retain(|utxo| !entry.spent.contains(&utxo.outpoint));
+
+        for utxo in &entry.created {
+            created.insert(utxo.outpoint);
+            if !rebuilt.iter().any(|other| other.outpoint == utxo.outpoint) {
+                rebuilt.push(utxo.clone());
+            }
+        }
+    }
+
+    let mut utxos = Vec::new();
+    for utxo in std::mem::take(&mut state.utxos) {
+        let in_history = created.contains(&utxo.outpoint);
+        let is_unspent = rebuilt.iter().any(|other| other.outpoint == utxo.outpoint);
+
+        if is_unspent || (!in_history && !force) {
+            if !in_history {
+                println!(
+                    "{}",
+                    style::error(format_args!(
+                        "Keeping UTXO (not in history; pass --force to remove): {}",
+                        utxo
+                    ))
+                );
+            }
+            utxos.push(utxo);
+        } else {
+            let reason = if in_history {
+                "spent"
+            } else {
+                "not in history"
+            };
+            println!(
+                "{}",
+                style::removed(format_args!("Removing UTXO ({}): {}", reason, utxo))
+            );
+            state.utxo_labels.remove(&utxo.outpoint);
+        }
+    }
+
+    for utxo in rebuilt {
+        if !utxos.iter().any(|other| other.outpoint == utxo.outpoint) {
+            println!("{}", style::added(format_args!("Restoring UTXO: {}", utxo)));
+            utxos.push(utxo);
+        }
+    }
+
+    state.utxos = utxos;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        let descriptor = Descriptor::from_str(
+            "tr(1ffa25da651d709df36d7563fffb5416a54ff2a9702ac66d8fde4c9d029d4c2f)",
+        )
+        .unwrap();
+        Utxo {
+            output: bitcoin::TxOut {
+                value,
+                script_pubkey: descriptor.script_pubkey(),
+            },
+            descriptor,
+            outpoint: bitcoin::OutPoint {
+                vout,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn state_with_history() -> State {
+        let mut state = State::new();
+        state.history.push(HistoryEntry {
+            txid: bitcoin::OutPoint::default().txid,
+            spent: Vec::new(),
+            created: vec![utxo(0, 1000), utxo(1, 2000)],
+            spend: None,
+        });
+        state.history.push(HistoryEntry {
+            txid: bitcoin::OutPoint::default().txid,
+            spent: vec![utxo(0, 1000).outpoint],
+            created: Vec::new(),
+            spend: None,
+        });
+        state
+    }
+
+    #[test]
+    fn utxo_not_in_history_is_kept() {
+        let mut state = state_with_history();
+        state.utxos = vec![utxo(0, 1000), utxo(7, 5000)];
+
+        rebuild_utxos(&mut state, false).unwrap();
+        let outpoints: Vec<_> = state.utxos.iter().map(|u| u.outpoint.vout).collect();
+        assert_eq!(outpoints, vec![7, 1]);
+    }
+
+    #[test]
+    fn force_removes_utxo_not_in_history() {
+        let mut state = state_with_history();
+        state.utxos = vec![utxo(7, 5000)];
+
+        rebuild_utxos(&mut state, true).unwrap();
+        let outpoints: Vec<_> = state.utxos.iter().map(|u| u.outpoint.vout).collect();
+        assert_eq!(outpoints, vec![1]);
+    }
+
+    #[test]
+    fn utxos_are_compared_by_outpoint() {
+        let mut state = state_with_history();
+        // Same outpoint as in the history, but a different amount
+        state.utxos = vec![utxo(1, 2500)];
+
+        rebuild_utxos(&mut state, false).unwrap();
+        assert_eq!(state.utxos, vec![utxo(1, 2500)]);
+    }
+}